@@ -0,0 +1,116 @@
+use anyhow::{Context, Result};
+use std::env;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+
+/// Root of everything the tool keeps between runs, following the XDG base
+/// directory spec where it applies.
+pub fn cache_root() -> PathBuf {
+  let base = env::var_os("XDG_CACHE_HOME")
+    .filter(|dir| !dir.is_empty())
+    .map(PathBuf::from)
+    .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+    .or_else(|| env::var_os("LOCALAPPDATA").map(PathBuf::from))
+    .unwrap_or_else(env::temp_dir);
+  base.join("cargo-single-pyo3")
+}
+
+/// A name for the generated Cargo project for `input`.
+///
+/// The name is keyed on the canonical path of the input and the current user,
+/// so files with the same name in different places, or built by different
+/// users sharing a fallback cache root, never share a project.
+pub fn project_name(input: &Path, crate_name: &str) -> Result<String> {
+  Ok(format!("{}-{:016x}", crate_name, fnv1a(&key(&[input])?)))
+}
+
+/// The directory holding the generated Cargo project for `input`.
+pub fn build_dir(input: &Path, crate_name: &str) -> Result<PathBuf> {
+  Ok(
+    cache_root()
+      .join("builds")
+      .join(project_name(input, crate_name)?),
+  )
+}
+
+/// The directory holding the generated workspace for a set of inputs, keyed
+/// the same way as [`build_dir`] but independent of their order.
+pub fn workspace_dir(inputs: &[&Path]) -> Result<PathBuf> {
+  Ok(
+    cache_root()
+      .join("workspaces")
+      .join(format!("{:016x}", fnv1a(&key(inputs)?))),
+  )
+}
+
+fn key(inputs: &[&Path]) -> Result<Vec<u8>> {
+  let user = user_id();
+
+  let mut paths = inputs
+    .iter()
+    .map(|input| {
+      input
+        .canonicalize()
+        .with_context(|| format!("Could not resolve {}", input.display()))
+    })
+    .collect::<Result<Vec<_>>>()?;
+  paths.sort();
+
+  let mut key = user.into_bytes();
+  for path in paths {
+    key.push(0);
+    key.extend(path.to_string_lossy().as_bytes());
+  }
+  Ok(key)
+}
+
+/// Identifies the current user: the uid on Unix, which unlike `$USER` cannot
+/// be changed from the environment, and the user name elsewhere.
+#[cfg(unix)]
+fn user_id() -> String {
+  // SAFETY: getuid has no preconditions and cannot fail.
+  unsafe { libc::getuid() }.to_string()
+}
+
+#[cfg(not(unix))]
+fn user_id() -> String {
+  env::var("USERNAME").unwrap_or_default()
+}
+
+/// The target directory shared by every generated project, so that pyo3 and
+/// other common dependencies are only compiled once.
+pub fn shared_target_dir() -> PathBuf {
+  cache_root().join("target")
+}
+
+/// Takes an exclusive lock on a build or target directory, waiting for any
+/// other invocation using the same directory to finish. The lock is released
+/// when the returned file is dropped.
+pub fn lock(dir: &Path) -> Result<File> {
+  fs::create_dir_all(dir)?;
+  let path = dir.join(".lock");
+  let file = OpenOptions::new()
+    .create(true)
+    .truncate(false)
+    .write(true)
+    .open(&path)?;
+
+  match file.try_lock() {
+    Ok(()) => {}
+    Err(TryLockError::WouldBlock) => {
+      eprintln!("Blocking waiting for file lock on {}", path.display());
+      file.lock()?;
+    }
+    Err(TryLockError::Error(err)) => return Err(err.into()),
+  }
+
+  Ok(file)
+}
+
+/// 64-bit FNV-1a, used instead of `DefaultHasher` since directory names must
+/// stay stable across Rust releases.
+fn fnv1a(bytes: &[u8]) -> u64 {
+  bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+    (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+  })
+}
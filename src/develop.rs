@@ -0,0 +1,93 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::wheel::Distribution;
+
+/// Installs `files` into `site_packages` along with the `.dist-info` metadata
+/// for `dist`, as if its wheel had been installed by pip, and returns the path
+/// of the first file.
+///
+/// Any previous install of the same distribution, whether by this tool or by
+/// pip, is removed first, as are stray copies of the module.
+pub fn install(
+  site_packages: &Path,
+  dist: &Distribution,
+  module_name: &str,
+  files: &[(String, Vec<u8>)],
+) -> Result<PathBuf> {
+  uninstall(site_packages, dist.name, module_name)?;
+
+  let mut files = files.to_vec();
+  files.push((
+    format!("{}/INSTALLER", dist.dist_info()),
+    format!("{}\n", env!("CARGO_PKG_NAME")).into_bytes(),
+  ));
+
+  for (name, contents) in dist.entries(&files) {
+    let path = site_packages.join(&name);
+    if let Some(parent) = path.parent() {
+      fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, contents).with_context(|| format!("Could not write {}", path.display()))?;
+  }
+
+  Ok(site_packages.join(&files[0].0))
+}
+
+fn uninstall(site_packages: &Path, name: &str, module_name: &str) -> Result<()> {
+  let entries = fs::read_dir(site_packages)
+    .with_context(|| format!("Could not read {}", site_packages.display()))?
+    .collect::<Result<Vec<_>, _>>()?;
+
+  for entry in entries {
+    let file_name = entry.file_name().to_string_lossy().into_owned();
+    if let Some(stem) = file_name.strip_suffix(".dist-info") {
+      let dist_name = stem.split('-').next().unwrap_or_default();
+      if normalize(dist_name) != normalize(name) {
+        continue;
+      }
+
+      if let Ok(record) = fs::read_to_string(entry.path().join("RECORD")) {
+        for line in record.lines() {
+          let path = line.split(',').next().unwrap_or_default().trim_matches('"');
+          if !path.is_empty() {
+            remove_file(&site_packages.join(path))?;
+          }
+        }
+      }
+      fs::remove_dir_all(entry.path())?;
+    } else if is_module_file(&file_name, module_name) {
+      remove_file(&entry.path())?;
+    }
+  }
+
+  Ok(())
+}
+
+/// Whether `file_name` is a build of the extension module `module_name`, for
+/// any interpreter, or its stub.
+fn is_module_file(file_name: &str, module_name: &str) -> bool {
+  let Some(rest) = file_name
+    .strip_prefix(module_name)
+    .and_then(|rest| rest.strip_prefix('.'))
+  else {
+    return false;
+  };
+  rest == "pyi" || matches!(rest.rsplit('.').next(), Some("so" | "pyd"))
+}
+
+/// Normalizes a distribution name for comparison, following PEP 503.
+fn normalize(name: &str) -> String {
+  name.to_lowercase().replace(['-', '.'], "_")
+}
+
+fn remove_file(path: &Path) -> Result<()> {
+  match fs::remove_file(path) {
+    Err(err) if err.kind() != ErrorKind::NotFound => {
+      Err(err).with_context(|| format!("Could not remove {}", path.display()))
+    }
+    _ => Ok(()),
+  }
+}
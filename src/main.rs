@@ -1,84 +1,240 @@
 use anyhow::{bail, Context, Result};
 use clap::clap_app;
-use serde::Serialize;
-use std::collections::HashMap;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fs;
-use std::path::Path;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 
-#[derive(Serialize)]
-struct CargoConfig {
-  package: CargoPackage,
-  lib: CargoLib,
-  dependencies: HashMap<String, CargoDependency>,
+use manifest::{
+  CargoConfig, CargoDependency, CargoLib, CargoPackage, CargoWorkspace, CargoWorkspaceConfig,
+  Header,
+};
+use pymodule::ApiEra;
+use target::Target;
+use wheel::Distribution;
+
+mod cache;
+mod develop;
+mod manifest;
+mod manylinux;
+mod pymodule;
+mod python;
+mod sources;
+mod stubs;
+mod target;
+mod watch;
+mod wheel;
+
+/// The source replacement printed by `cargo vendor`, kept in `.cargo` so it
+/// can be added to the config whenever the project is regenerated.
+const VENDOR_CONFIG: &str = "vendor.toml";
+
+/// Writes the `.cargo/config.toml` every generated project or workspace needs,
+/// including the source replacement for its vendored dependencies if the
+/// `vendor` subcommand has been run on it and the settings for the target
+/// selected by `opts`.
+fn write_cargo_config(dir: &Path, opts: &BuildOptions) -> Result<()> {
+  let dot_cargo = dir.join(".cargo");
+  fs::create_dir_all(&dot_cargo)?;
+  let mut config = String::from(
+    r#"
+[target.x86_64-apple-darwin]
+rustflags = [
+  "-C", "link-arg=-undefined",
+  "-C", "link-arg=dynamic_lookup",
+]
+
+[target.aarch64-apple-darwin]
+rustflags = [
+  "-C", "link-arg=-undefined",
+  "-C", "link-arg=dynamic_lookup",
+]"#,
+  );
+  if let Some(target) = &opts.target {
+    let target_config = target.cargo_config();
+    if !target_config.is_empty() {
+      config.push_str("\n\n");
+      config.push_str(&target_config);
+    }
+  }
+  if let Ok(sources) = fs::read_to_string(dot_cargo.join(VENDOR_CONFIG)) {
+    config.push_str("\n\n");
+    config.push_str(&sources);
+  }
+  fs::write(dot_cargo.join("config.toml"), config)?;
+
+  Ok(())
 }
 
-#[derive(Serialize)]
-struct CargoPackage {
-  name: String,
-  version: String,
-  edition: String,
+/// The pyo3 version used when a file has none recorded yet and was written
+/// against the current API.
+const PYO3_VERSION: &str = "0.22";
+
+/// The pyo3 version to build `input` against when the header does not give
+/// one: the one given with `--pyo3`, else the one recorded for the file, else
+/// one matching the API its source `src` is written against.
+///
+/// The version is recorded in the lockfile with `--lock`, and in the pin file
+/// next to the input otherwise.
+fn pyo3_version(input: &Path, src: &str, opts: &BuildOptions) -> Result<String> {
+  let recorded = match opts.pyo3 {
+    Some("latest") => return Ok("*".to_owned()),
+    Some(version) => return Ok(version.to_owned()),
+    None if opts.lock => manifest::locked_version(&lock_path(input), crate_name(input)?, "pyo3"),
+    None => fs::read_to_string(pin_path(input))
+      .ok()
+      .map(|pinned| pinned.trim().to_owned()),
+  };
+  Ok(recorded.unwrap_or_else(|| match pymodule::api_era(src) {
+    Some((ApiEra::GilRefs, _)) => "0.20".to_owned(),
+    _ => PYO3_VERSION.to_owned(),
+  }))
 }
 
-#[derive(Serialize)]
-struct CargoLib {
-  name: String,
-  #[serde(rename = "crate-type")]
-  crate_type: Vec<String>,
+/// Which pyo3 `input` is built against, up to semver compatibility. Inputs
+/// with different requirements cannot share a lockfile, since pyo3 links to
+/// Python and cargo allows only one version of such a crate.
+fn pyo3_requirement(input: &Path, opts: &BuildOptions) -> Result<String> {
+  let src = fs::read_to_string(input)?;
+  let (header, src) = manifest::parse_header(input, &src)?;
+  let pyo3 = match header
+    .dependencies
+    .get("pyo3")
+    .filter(|pyo3| pyo3.has_source())
+  {
+    Some(pyo3) => pyo3,
+    // Every input without a pyo3 of its own gets the same one from the
+    // command line.
+    None if opts.pyo3_path.is_some() || opts.pyo3_git.is_some() => return Ok("default".into()),
+    None => {
+      return Ok(compatible_version(&pyo3_version(input, &src, opts)?));
+    }
+  };
+  Ok(if let Some(path) = &pyo3.path {
+    format!(
+      "path {}",
+      input.parent().unwrap_or(Path::new("")).join(path).display()
+    )
+  } else if let Some(git) = &pyo3.git {
+    let reference = pyo3
+      .rev
+      .as_ref()
+      .or(pyo3.branch.as_ref())
+      .or(pyo3.tag.as_ref());
+    format!("git {} {}", git, reference.map_or("", String::as_str))
+  } else {
+    compatible_version(pyo3.version.as_deref().unwrap_or("*"))
+  })
 }
 
-#[derive(Serialize)]
-struct CargoDependency {
-  version: String,
-  features: Vec<String>,
-  git: Option<String>,
-  branch: Option<String>,
+/// The semver-compatible part of a version requirement such as `0.22.2` or
+/// `^1.2`: `0.22` or `1`.
+fn compatible_version(version: &str) -> String {
+  let version = version.trim().trim_start_matches(['^', '~', '=']).trim();
+  let parts: Vec<&str> = version.split('.').collect();
+  match parts.as_slice() {
+    ["0", minor, ..] => format!("0.{}", minor),
+    [major, ..] => (*major).to_owned(),
+    [] => version.to_owned(),
+  }
 }
 
-fn collect_deps(input: &Path) -> Result<Vec<String>> {
-  let src = String::from_utf8(fs::read(input)?)?;
-  let mut deps = Vec::new();
-  for line in src.lines() {
-    if !line.starts_with("// ") {
-      break;
+/// The pyo3 dependency to build against when the header does not give one,
+/// at `version` unless another source is selected.
+fn pyo3_dependency(opts: &BuildOptions, version: &str) -> CargoDependency {
+  let mut pyo3 = if let Some(path) = &opts.pyo3_path {
+    CargoDependency {
+      path: Some(path.to_string_lossy().into_owned()),
+      ..Default::default()
     }
+  } else if let Some(git) = opts.pyo3_git {
+    CargoDependency {
+      git: Some(git.into()),
+      rev: opts.pyo3_rev.map(Into::into),
+      ..Default::default()
+    }
+  } else if opts.pyo3 == Some("github") {
+    CargoDependency {
+      version: Some("*".into()),
+      git: Some("https://github.com/PyO3/pyo3".into()),
+      branch: Some("main".into()),
+      ..Default::default()
+    }
+  } else {
+    CargoDependency {
+      version: Some(version.into()),
+      ..Default::default()
+    }
+  };
 
-    let dep = line.chars().skip(3).collect::<String>();
-    deps.push(dep)
+  pyo3.features.push("extension-module".into());
+  if let Some((major, minor)) = opts.abi3 {
+    pyo3.features.push(format!("abi3-py{}{}", major, minor));
   }
-
-  Ok(deps)
+  // Extension modules on Windows link against python3.dll or pythonXY.dll,
+  // which a cross build has no copy of.
+  if opts
+    .target
+    .as_ref()
+    .is_some_and(|target| target.is_cross && target.is_windows())
+  {
+    pyo3.features.push("generate-import-lib".into());
+  }
+  pyo3
+    .features
+    .extend(opts.pyo3_features.iter().map(|feature| feature.to_string()));
+  pyo3
 }
 
 fn create_dir(
   cargo_dir: &Path,
   input: &Path,
+  src: &str,
   crate_name: &str,
   module_name: &str,
-  deps: &[String],
-  pyo3_version: &str,
+  header: Header,
+  pyo3: CargoDependency,
 ) -> Result<()> {
-  let mut dependencies = HashMap::new();
-  let (version, git, branch) = if pyo3_version == "github" {
-    (
-      "*".into(),
-      Some("https://github.com/PyO3/pyo3".into()),
-      Some("main".into()),
-    )
-  } else {
-    (pyo3_version.into(), None, None)
+  let mut dependencies = header.dependencies;
+
+  // A pyo3 entry in the header takes precedence, except that one which only
+  // lists features, e.g. `pyo3 = { features = ["chrono"] }`, keeps the default
+  // source. Either way it gets the features the build relies on.
+  let pyo3 = match dependencies.remove("pyo3") {
+    Some(mut entry) => {
+      if !entry.has_source() {
+        entry.version = pyo3.version;
+        entry.git = pyo3.git;
+        entry.branch = pyo3.branch;
+        entry.rev = pyo3.rev;
+        entry.path = pyo3.path;
+      }
+      for feature in pyo3.features {
+        if !entry.features.contains(&feature) {
+          entry.features.push(feature);
+        }
+      }
+      entry
+    }
+    None => pyo3,
   };
+  dependencies.insert("pyo3".into(), pyo3);
 
-  dependencies.insert(
-    "pyo3".into(),
-    CargoDependency {
-      features: vec!["extension-module".into()],
-      version,
-      git,
-      branch,
-    },
-  );
+  // The project is generated elsewhere, so relative paths in the header are
+  // resolved against the directory of the input.
+  let input_dir = input.parent().unwrap_or_else(|| Path::new(""));
+  for dependency in dependencies.values_mut() {
+    if let Some(path) = &mut dependency.path {
+      *path = env::current_dir()?
+        .join(input_dir)
+        .join(&*path)
+        .to_string_lossy()
+        .into_owned();
+    }
+  }
 
   let config = CargoConfig {
     package: CargoPackage {
@@ -96,98 +252,935 @@ fn create_dir(
   let src_dir = &cargo_dir.join("src");
   fs::create_dir_all(src_dir)?;
 
-  let mut config_contents = toml::to_string(&config)?;
-  config_contents.push_str(&format!("\n[dependencies]\n{}", deps.join("\n")));
+  let mut manifest = toml::Value::try_from(&config)?;
+  let root = manifest.as_table_mut().context("manifest is not a table")?;
+  manifest::merge(root, header.sections);
 
-  fs::write(cargo_dir.join("Cargo.toml"), config_contents)?;
-  fs::copy(input, src_dir.join("lib.rs"))?;
+  fs::write(cargo_dir.join("Cargo.toml"), toml::to_string(&manifest)?)?;
+  fs::write(src_dir.join("lib.rs"), src)?;
 
-  let dot_cargo = cargo_dir.join(".cargo");
-  fs::create_dir_all(&dot_cargo)?;
-  fs::write(
-    dot_cargo.join("config.toml"),
-    r#"
-[target.x86_64-apple-darwin]
-rustflags = [
-  "-C", "link-arg=-undefined",
-  "-C", "link-arg=dynamic_lookup",
-]
-
-[target.aarch64-apple-darwin]
-rustflags = [
-  "-C", "link-arg=-undefined",
-  "-C", "link-arg=dynamic_lookup",
-]"#,
-  )?;
+  for file in sources::local_files(input) {
+    let dst = src_dir.join(&file);
+    if let Some(parent) = dst.parent() {
+      fs::create_dir_all(parent)?;
+    }
+    fs::copy(input_dir.join(&file), dst)?;
+  }
 
   Ok(())
 }
 
-fn run() -> Result<()> {
-  let clap_args = env::args().skip(1).collect::<Vec<_>>();
-  let matches = clap_app! {single_pyo3 =>
-    (version: "0.1")
-    (author: "Will Crichton <crichton.will@gmail.com>")
-    (about: "Builds a single Rust file as a Python module via pyo3")
-    (@arg verbose: -v --verbose)
-    (@arg release: --release)
-    (@arg pyo3: --pyo3 +takes_value "Pyo3 version. Use \"github\" to get latest from main branch.")
-    (@arg INPUT: +required "Input file")
+/// Where to write the built module. `out` names a file if it has an extension
+/// and is not an existing directory, and a directory otherwise.
+fn output_path(
+  out: Option<&str>,
+  next_to_input: bool,
+  input: &Path,
+  file_name: &str,
+) -> Result<PathBuf> {
+  let path = match out {
+    Some(out) => {
+      let out = Path::new(out);
+      let is_dir = out.is_dir()
+        || out.extension().is_none()
+        || out.to_string_lossy().ends_with(std::path::is_separator);
+      if is_dir {
+        out.join(file_name)
+      } else {
+        out.to_owned()
+      }
+    }
+    None if next_to_input => input.with_file_name(file_name),
+    None => PathBuf::from(file_name),
+  };
+
+  if let Some(parent) = path
+    .parent()
+    .filter(|parent| !parent.as_os_str().is_empty())
+  {
+    fs::create_dir_all(parent)?;
   }
-  .get_matches_from(&clap_args);
 
-  let verbose = matches.is_present("verbose");
-  let input = matches.value_of("INPUT").unwrap();
-  let input = Path::new(input);
+  Ok(path)
+}
+
+/// What to produce from each built module.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+  /// Copy the module out as is.
+  Module,
+  /// Package the module as a wheel.
+  Wheel,
+  /// Install the module into the target interpreter's site-packages.
+  Develop,
+}
+
+/// Settings shared by every build in one invocation.
+struct BuildOptions<'a> {
+  mode: Mode,
+  verbose: bool,
+  release: bool,
+  /// The pyo3 version given with `--pyo3`, which may also be `latest` or
+  /// `github`.
+  pyo3: Option<&'a str>,
+  /// A git repository or local checkout to take pyo3 from instead of
+  /// crates.io.
+  pyo3_git: Option<&'a str>,
+  pyo3_rev: Option<&'a str>,
+  pyo3_path: Option<PathBuf>,
+  /// Extra pyo3 features requested on the command line.
+  pyo3_features: Vec<&'a str>,
+  python: PathBuf,
+  isolated_target: bool,
+  plain_suffix: bool,
+  out: Option<&'a str>,
+  next_to_input: bool,
+  /// The minimum Python version for a limited API build.
+  abi3: Option<(u32, u32)>,
+  auto_module: bool,
+  stubs: bool,
+  /// Keep each file's lockfile next to it, and with `locked`, refuse to
+  /// build if it is missing or out of date.
+  lock: bool,
+  locked: bool,
+  /// Passed on to cargo, to build without network access.
+  offline: bool,
+  frozen: bool,
+  /// Vendor the dependencies of each project instead of building it.
+  vendor: bool,
+  /// The target to build for with `--target`.
+  target: Option<Target>,
+  /// The library directory of the target's Python when cross compiling.
+  cross_lib_dir: Option<PathBuf>,
+  /// The manylinux policy modules must comply with.
+  manylinux: Option<&'static manylinux::Policy>,
+  /// Arguments given after `--`, passed on to `cargo build` verbatim.
+  cargo_args: Vec<&'a str>,
+}
 
-  let crate_name = input
+/// A generated Cargo project for one input file.
+struct Project<'a> {
+  input: &'a Path,
+  module_name: String,
+  /// `package.version` and `package.description` from the header, if set.
+  version: String,
+  summary: Option<String>,
+  /// Contents of the module's `.pyi` stub, if one was requested.
+  stubs: Option<String>,
+  /// Where to record the pyo3 version the build resolves, if the file does
+  /// not choose one itself.
+  pyo3_pin: Option<PathBuf>,
+}
+
+fn crate_name(input: &Path) -> Result<&str> {
+  input
     .file_stem()
     .context("No file stem")?
     .to_str()
-    .context("to_string")?;
+    .context("to_string")
+}
+
+/// Generates the Cargo project for `input` in `cargo_dir`. When `root` is
+/// given, the header's `ROOT_SECTIONS` are merged into it rather than into
+/// the project's own manifest.
+fn generate<'a>(
+  input: &'a Path,
+  cargo_dir: &Path,
+  opts: &BuildOptions,
+  root: Option<&mut toml::value::Table>,
+) -> Result<Project<'a>> {
+  let crate_name = crate_name(input)?;
   let module_name = crate_name.replace('-', "_");
 
-  let cargo_dir = &env::temp_dir().join(crate_name);
-  if verbose {
-    println!("{}", cargo_dir.display());
+  let src = fs::read_to_string(input)?;
+  let (mut header, mut src) = manifest::parse_header(input, &src)?;
+  if opts.auto_module {
+    if let Some(init) = pymodule::generate(&src, &module_name) {
+      src.push_str(&init);
+    }
   }
+  pymodule::check(input, &src, &module_name)?;
+  if let Some(root) = root {
+    for section in manifest::ROOT_SECTIONS {
+      if let Some(overrides) = header.sections.remove(*section) {
+        let overrides = toml::value::Table::from_iter([(section.to_string(), overrides)]);
+        if let Err(key) = manifest::merge_disjoint(root, overrides) {
+          bail!(
+            "{} sets `{}` differently from another input in the same workspace, build them separately",
+            input.display(),
+            key
+          );
+        }
+      }
+    }
+  }
+
+  let package = header.sections.get("package");
+  let package_str = |key: &str| {
+    package
+      .and_then(|package| package.get(key))
+      .and_then(toml::Value::as_str)
+      .map(str::to_owned)
+  };
+  let version = package_str("version").unwrap_or_else(|| "0.1.0".into());
+  let summary = package_str("description");
 
-  let deps = collect_deps(input)?;
+  // Unless the header or the command line say otherwise, pyo3 stays at the
+  // version the file was first built with, which is picked to match the API
+  // the file is written against.
+  let header_pyo3 = header
+    .dependencies
+    .get("pyo3")
+    .filter(|pyo3| pyo3.has_source());
+  let pyo3_version = pyo3_version(input, &src, opts)?;
+  let is_registry = opts.pyo3_git.is_none() && opts.pyo3_path.is_none();
+  let checked_version = match header_pyo3 {
+    Some(pyo3) => pyo3
+      .version
+      .as_deref()
+      .filter(|_| pyo3.git.is_none() && pyo3.path.is_none()),
+    None => Some(pyo3_version.as_str()).filter(|_| is_registry),
+  };
+  if let Some(warning) =
+    checked_version.and_then(|version| pymodule::api_mismatch(input, &src, version))
+  {
+    eprintln!("warning: {}", warning);
+  }
+  let pinned = header_pyo3.is_none()
+    && is_registry
+    && matches!(opts.pyo3, None | Some("latest"))
+    && !opts.lock;
 
   create_dir(
     cargo_dir,
     input,
+    &src,
     crate_name,
     &module_name,
-    &deps,
-    matches.value_of("pyo3").unwrap_or("*"),
+    header,
+    pyo3_dependency(opts, &pyo3_version),
   )?;
+  write_cargo_config(cargo_dir, opts)?;
 
-  let is_release = matches.is_present("release");
-  let mut args = vec!["build"];
-  if is_release {
+  let stubs = if opts.stubs {
+    stubs::generate(&src)
+  } else {
+    None
+  };
+
+  Ok(Project {
+    input,
+    module_name,
+    version,
+    summary,
+    stubs,
+    pyo3_pin: pinned.then(|| pin_path(input)),
+  })
+}
+
+/// The target directory to build in, given the one that would be private to
+/// the project or workspace.
+fn target_dir(isolated: PathBuf, opts: &BuildOptions) -> Result<PathBuf> {
+  let target_dir = if opts.isolated_target {
+    isolated
+  } else {
+    match env::var_os("CARGO_TARGET_DIR") {
+      Some(dir) => env::current_dir()?.join(dir),
+      None => cache::shared_target_dir(),
+    }
+  };
+  if opts.verbose {
+    println!("{}", target_dir.display());
+  }
+  Ok(target_dir)
+}
+
+/// Flags passed to every cargo command that resolves dependencies.
+fn cargo_flags(opts: &BuildOptions) -> Vec<&'static str> {
+  let mut flags = Vec::new();
+  if opts.locked {
+    flags.push("--locked");
+  }
+  if opts.offline {
+    flags.push("--offline");
+  }
+  if opts.frozen {
+    flags.push("--frozen");
+  }
+  flags
+}
+
+/// The part of a cargo JSON message needed to find built libraries.
+#[derive(Deserialize)]
+struct CargoMessage {
+  reason: String,
+  target: Option<CargoTarget>,
+  #[serde(default)]
+  filenames: Vec<PathBuf>,
+}
+
+#[derive(Deserialize)]
+struct CargoTarget {
+  name: String,
+  kind: Vec<String>,
+}
+
+/// Runs `cargo build` for the project or workspace in `cargo_dir`, returning
+/// the cdylib built for each library name, as reported by cargo.
+fn cargo_build(
+  cargo_dir: &Path,
+  target_dir: &Path,
+  opts: &BuildOptions,
+) -> Result<HashMap<String, PathBuf>> {
+  let mut args = vec!["build", "--message-format=json-render-diagnostics"];
+  let has_release = opts
+    .cargo_args
+    .iter()
+    .any(|arg| matches!(*arg, "--release" | "-r"));
+  if opts.release && !has_release && cargo_arg(&opts.cargo_args, "profile").is_none() {
     args.push("--release");
   }
-  let status = Command::new("cargo")
+  if let Some(target) = &opts.target {
+    args.extend(["--target", &target.triple]);
+  }
+  args.extend(cargo_flags(opts));
+  args.extend(&opts.cargo_args);
+  let mut command = Command::new("cargo");
+  command
     .args(&args)
     .current_dir(cargo_dir)
+    .env("CARGO_TARGET_DIR", target_dir)
+    .env("PYO3_PYTHON", &opts.python)
+    .stdout(Stdio::piped())
+    .stderr(Stdio::inherit());
+
+  // pyo3 cannot ask the host interpreter about the target's Python, so it
+  // reads the target's sysconfigdata if given, and otherwise assumes the
+  // same version as the selected interpreter.
+  if opts.target.as_ref().is_some_and(|target| target.is_cross) {
+    if let Some(lib_dir) = &opts.cross_lib_dir {
+      command.env("PYO3_CROSS_LIB_DIR", lib_dir);
+    } else if env::var_os("PYO3_CROSS_PYTHON_VERSION").is_none() {
+      let (major, minor) = python::version(&opts.python)?;
+      command.env("PYO3_CROSS_PYTHON_VERSION", format!("{}.{}", major, minor));
+    }
+  }
+  let mut child = command.spawn()?;
+
+  // Diagnostics are rendered on stderr, leaving one JSON message per line on
+  // stdout. On Windows the import library is reported next to the DLL.
+  let mut artifacts = HashMap::new();
+  let stdout = child.stdout.take().context("cargo has no stdout")?;
+  for line in BufReader::new(stdout).lines() {
+    let Ok(message) = serde_json::from_str::<CargoMessage>(&line?) else {
+      continue;
+    };
+    let Some(target) = message.target else {
+      continue;
+    };
+    if message.reason != "compiler-artifact" || !target.kind.iter().any(|kind| kind == "cdylib") {
+      continue;
+    }
+    let library = message.filenames.into_iter().find(|file| {
+      file
+        .extension()
+        .is_some_and(|ext| ext == "so" || ext == "dylib" || ext == "dll")
+    });
+    if let Some(library) = library {
+      artifacts.insert(target.name, library);
+    }
+  }
+
+  if !child.wait()?.success() {
+    bail!("cargo failed");
+  }
+
+  Ok(artifacts)
+}
+
+/// Updates pyo3 to the newest version in the lockfile of `cargo_dir`, so that
+/// `--pyo3 latest` does not keep the version resolved by an earlier build.
+fn update_pyo3(cargo_dir: &Path, opts: &BuildOptions) -> Result<()> {
+  if opts.pyo3 != Some("latest") || !cargo_dir.join("Cargo.lock").exists() {
+    return Ok(());
+  }
+
+  let status = Command::new("cargo")
+    .args(["update", "--package", "pyo3"])
+    .args(cargo_flags(opts))
+    .current_dir(cargo_dir)
     .stdout(Stdio::inherit())
     .stderr(Stdio::inherit())
     .status()?;
 
   if !status.success() {
-    bail!("cargo failed");
+    bail!("cargo update failed");
+  }
+
+  Ok(())
+}
+
+/// Vendors the dependencies of the project or workspace in `cargo_dir` into
+/// its `vendor` directory, and replaces their sources with it so that later
+/// builds need no network access. Returns the `vendor` directory.
+fn vendor(cargo_dir: &Path, opts: &BuildOptions) -> Result<PathBuf> {
+  // The config is printed on stdout, and cargo's messages are only shown if
+  // asked for, since they end by saying to add it by hand.
+  let output = Command::new("cargo")
+    .arg("vendor")
+    .args(cargo_flags(opts))
+    .arg("vendor")
+    .current_dir(cargo_dir)
+    .output()?;
+
+  if opts.verbose || !output.status.success() {
+    eprint!("{}", String::from_utf8_lossy(&output.stderr));
+  }
+  if !output.status.success() {
+    bail!("cargo vendor failed");
+  }
+
+  fs::write(cargo_dir.join(".cargo").join(VENDOR_CONFIG), output.stdout)?;
+  write_cargo_config(cargo_dir, opts)?;
+
+  Ok(cargo_dir.join("vendor"))
+}
+
+/// The lockfile kept next to `input` with `--lock`, e.g. `foo.rs.lock`.
+fn lock_path(input: &Path) -> PathBuf {
+  let mut path = input.as_os_str().to_owned();
+  path.push(".lock");
+  PathBuf::from(path)
+}
+
+/// The file next to `input` recording the pyo3 version last resolved for it,
+/// e.g. `foo.rs.pyo3`, so that later builds keep using it rather than
+/// whatever is newest.
+fn pin_path(input: &Path) -> PathBuf {
+  let mut path = input.as_os_str().to_owned();
+  path.push(".pyo3");
+  PathBuf::from(path)
+}
+
+/// Copies the lockfile kept next to `input` into `cargo_dir`, if there is one.
+fn restore_lock(input: &Path, cargo_dir: &Path, opts: &BuildOptions) -> Result<()> {
+  let lock = lock_path(input);
+  if lock.exists() {
+    fs::copy(&lock, cargo_dir.join("Cargo.lock"))
+      .with_context(|| format!("Could not read {}", lock.display()))?;
+  } else if opts.locked {
+    bail!(
+      "{} does not exist. Build with --lock to create it",
+      lock.display()
+    );
   }
+  Ok(())
+}
 
-  let lib_name = format!("lib{}.{}", module_name, env::consts::DLL_EXTENSION);
-  let release = if is_release { "release" } else { "debug" };
-  let lib_src_path = cargo_dir.join("target").join(release).join(lib_name);
-  let lib_dst_path = format!("{}.so", module_name);
-  fs::copy(lib_src_path, lib_dst_path)?;
+/// Records the pyo3 version each of `projects` was built against, as
+/// resolved in the lockfile of `cargo_dir`.
+fn pin_pyo3(cargo_dir: &Path, projects: &[Project]) -> Result<()> {
+  for project in projects {
+    let Some(pin) = &project.pyo3_pin else {
+      continue;
+    };
+    let lock = cargo_dir.join("Cargo.lock");
+    if let Some(version) = manifest::locked_version(&lock, crate_name(project.input)?, "pyo3") {
+      // The module is already built, so a read-only checkout only costs the
+      // pin.
+      if let Err(err) = fs::write(pin, version) {
+        eprintln!(
+          "warning: could not record the pyo3 version in {}: {}",
+          pin.display(),
+          err
+        );
+      }
+    }
+  }
 
   Ok(())
 }
 
+/// The value of the last `--name value` or `--name=value` option in `args`.
+fn cargo_arg<'a>(args: &[&'a str], name: &str) -> Option<&'a str> {
+  let flag = format!("--{}", name);
+  let mut value = None;
+  let mut args = args.iter();
+  while let Some(arg) = args.next() {
+    if *arg == flag {
+      value = args.next().copied();
+    } else if let Some(rest) = arg
+      .strip_prefix(&flag)
+      .and_then(|rest| rest.strip_prefix('='))
+    {
+      value = Some(rest);
+    }
+  }
+  value
+}
+
+/// The file name suffix to give the module.
+fn module_suffix(opts: &BuildOptions) -> String {
+  let cross_target = opts.target.as_ref().filter(|target| target.is_cross);
+  let plain_suffix = cross_target.map_or_else(python::plain_suffix, Target::plain_suffix);
+  if opts.plain_suffix {
+    plain_suffix.to_owned()
+  } else if opts.abi3.is_some() {
+    cross_target
+      .map_or_else(python::abi3_suffix, Target::abi3_suffix)
+      .to_owned()
+  } else {
+    let suffix = match cross_target {
+      Some(target) => match opts
+        .cross_lib_dir
+        .as_deref()
+        .and_then(target::sysconfig_ext_suffix)
+      {
+        Some(suffix) => Ok(suffix),
+        None => python::version(&opts.python).map(|version| target.ext_suffix(version)),
+      },
+      None => python::ext_suffix(&opts.python),
+    };
+    suffix.unwrap_or_else(|err| {
+      eprintln!("warning: {:#}, falling back to {}", err, plain_suffix);
+      plain_suffix.to_owned()
+    })
+  }
+}
+
+/// The wheel tag for modules built with `opts`. The interpreter and ABI are
+/// those of the selected interpreter even when cross compiling.
+fn wheel_tag(opts: &BuildOptions) -> Result<String> {
+  let mut tag = python::wheel_tag(&opts.python, opts.abi3)?;
+  if let Some(target) = opts.target.as_ref().filter(|target| target.is_cross) {
+    tag.platform = target.wheel_platform();
+  }
+  if let Some(policy) = opts.manylinux {
+    tag.platform = policy.platform_tag(&tag.platform)?;
+  }
+  Ok(tag.to_string())
+}
+
+/// Produces the output for `project` from its module in `artifacts`
+/// according to `opts.mode`, returning where it was written.
+fn deliver(
+  project: &Project,
+  artifacts: &HashMap<String, PathBuf>,
+  opts: &BuildOptions,
+) -> Result<PathBuf> {
+  let artifact = artifacts
+    .get(&project.module_name)
+    .with_context(|| format!("cargo did not report a library for {}", project.module_name))?;
+  if let Some(policy) = opts.manylinux {
+    policy.audit(artifact)?;
+  }
+  match opts.mode {
+    Mode::Module => copy_out(project, artifact, opts),
+    Mode::Wheel => write_wheel(project, artifact, opts),
+    Mode::Develop => install(project, artifact, opts),
+  }
+}
+
+/// Copies the built module for `project` out, along with its stub if there is
+/// one.
+fn copy_out(project: &Project, artifact: &Path, opts: &BuildOptions) -> Result<PathBuf> {
+  let lib_dst_path = output_path(
+    opts.out,
+    opts.next_to_input,
+    project.input,
+    &format!("{}{}", project.module_name, module_suffix(opts)),
+  )?;
+  fs::copy(artifact, &lib_dst_path)
+    .with_context(|| format!("Could not write {}", lib_dst_path.display()))?;
+
+  if let Some(stubs) = &project.stubs {
+    let stubs_path = lib_dst_path.with_file_name(format!("{}.pyi", project.module_name));
+    fs::write(&stubs_path, stubs)
+      .with_context(|| format!("Could not write {}", stubs_path.display()))?;
+  }
+
+  Ok(lib_dst_path)
+}
+
+/// The files a distribution of `project` installs: the module itself, named
+/// for the target interpreter, and its stub if there is one.
+fn module_files(
+  project: &Project,
+  artifact: &Path,
+  opts: &BuildOptions,
+) -> Result<Vec<(String, Vec<u8>)>> {
+  let mut files = vec![(
+    format!("{}{}", project.module_name, module_suffix(opts)),
+    fs::read(artifact)?,
+  )];
+  if let Some(stubs) = &project.stubs {
+    files.push((
+      format!("{}.pyi", project.module_name),
+      stubs.clone().into_bytes(),
+    ));
+  }
+  Ok(files)
+}
+
+fn distribution<'a>(project: &'a Project, tag: &'a str) -> Distribution<'a> {
+  Distribution {
+    name: &project.module_name,
+    version: &project.version,
+    summary: project.summary.as_deref(),
+    tag,
+  }
+}
+
+/// Packages the built module for `project` as a wheel.
+fn write_wheel(project: &Project, artifact: &Path, opts: &BuildOptions) -> Result<PathBuf> {
+  let tag = wheel_tag(opts)?;
+  let dist = distribution(project, &tag);
+  let wheel_path = output_path(
+    opts.out,
+    opts.next_to_input,
+    project.input,
+    &dist.file_name(),
+  )?;
+  dist
+    .write_wheel(&wheel_path, &module_files(project, artifact, opts)?)
+    .with_context(|| format!("Could not write {}", wheel_path.display()))?;
+
+  Ok(wheel_path)
+}
+
+/// Installs the built module for `project` into the target interpreter's
+/// site-packages, replacing any previous install.
+fn install(project: &Project, artifact: &Path, opts: &BuildOptions) -> Result<PathBuf> {
+  let tag = wheel_tag(opts)?;
+  let site_packages = python::site_packages(&opts.python)?;
+  develop::install(
+    &site_packages,
+    &distribution(project, &tag),
+    &project.module_name,
+    &module_files(project, artifact, opts)?,
+  )
+}
+
+/// Generates a project for `input`, builds it, and delivers the module.
+fn build(input: &Path, opts: &BuildOptions) -> Result<PathBuf> {
+  let cargo_dir = &cache::build_dir(input, crate_name(input)?)?;
+  if opts.verbose {
+    println!("{}", cargo_dir.display());
+  }
+  let _lock = cache::lock(cargo_dir)?;
+  let target_dir = target_dir(cargo_dir.join("target"), opts)?;
+
+  let project = generate(input, cargo_dir, opts, None)?;
+
+  // Modules sharing a target directory also share the name of their final
+  // artifact if their files are named the same, so hold the lock until it has
+  // been copied out.
+  let _target_lock = cache::lock(&target_dir)?;
+  if opts.lock {
+    restore_lock(input, cargo_dir, opts)?;
+  }
+  if opts.vendor {
+    return vendor(cargo_dir, opts);
+  }
+  update_pyo3(cargo_dir, opts)?;
+  let artifacts = cargo_build(cargo_dir, &target_dir, opts)?;
+  pin_pyo3(cargo_dir, std::slice::from_ref(&project))?;
+  if opts.lock {
+    let lock = lock_path(input);
+    fs::copy(cargo_dir.join("Cargo.lock"), &lock)
+      .with_context(|| format!("Could not write {}", lock.display()))?;
+  }
+  deliver(&project, &artifacts, opts)
+}
+
+/// Generates one workspace with a member project per input, so that cargo
+/// builds them in parallel with shared dependencies, and delivers every
+/// module.
+fn build_workspace(inputs: &[&Path], opts: &BuildOptions) -> Result<Vec<PathBuf>> {
+  let workspace_dir = &cache::workspace_dir(inputs)?;
+  if opts.verbose {
+    println!("{}", workspace_dir.display());
+  }
+  let _lock = cache::lock(workspace_dir)?;
+  let target_dir = target_dir(workspace_dir.join("target"), opts)?;
+
+  let mut root = toml::value::Table::new();
+  let mut projects: Vec<Project> = Vec::new();
+  let mut members = Vec::new();
+  for input in inputs {
+    let member = cache::project_name(input, crate_name(input)?)?;
+    let project = generate(input, &workspace_dir.join(&member), opts, Some(&mut root))?;
+    if let Some(other) = projects
+      .iter()
+      .find(|other| other.module_name == project.module_name)
+    {
+      bail!(
+        "{} and {} would both build a module named `{}`",
+        other.input.display(),
+        input.display(),
+        project.module_name
+      );
+    }
+    projects.push(project);
+    members.push(member);
+  }
+
+  let config = CargoWorkspaceConfig {
+    workspace: CargoWorkspace {
+      members,
+      resolver: "2".into(),
+    },
+    sections: root,
+  };
+  fs::write(workspace_dir.join("Cargo.toml"), toml::to_string(&config)?)?;
+  write_cargo_config(workspace_dir, opts)?;
+  if opts.vendor {
+    return Ok(vec![vendor(workspace_dir, opts)?]);
+  }
+
+  let _target_lock = cache::lock(&target_dir)?;
+  update_pyo3(workspace_dir, opts)?;
+  let artifacts = cargo_build(workspace_dir, &target_dir, opts)?;
+  pin_pyo3(workspace_dir, &projects)?;
+  projects
+    .iter()
+    .map(|project| deliver(project, &artifacts, opts))
+    .collect()
+}
+
+/// Builds every input, sharing a workspace between inputs that need the same
+/// pyo3.
+///
+/// A workspace has a single lockfile, so with `--lock` every input is built
+/// in its own project instead.
+fn build_all(inputs: &[&Path], opts: &BuildOptions) -> Result<Vec<PathBuf>> {
+  match inputs {
+    [input] => Ok(vec![build(input, opts)?]),
+    _ if opts.lock => inputs.iter().map(|input| build(input, opts)).collect(),
+    _ => {
+      let mut groups: Vec<(String, Vec<&Path>)> = Vec::new();
+      for input in inputs {
+        let requirement = pyo3_requirement(input, opts)?;
+        match groups.iter_mut().find(|(other, _)| *other == requirement) {
+          Some((_, group)) => group.push(input),
+          None => groups.push((requirement, vec![input])),
+        }
+      }
+      let mut outputs = Vec::new();
+      for (_, group) in groups {
+        match group.as_slice() {
+          [input] => outputs.push(build(input, opts)?),
+          group => outputs.extend(build_workspace(group, opts)?),
+        }
+      }
+      Ok(outputs)
+    }
+  }
+}
+
+fn run() -> Result<()> {
+  let clap_args = env::args().skip(1).collect::<Vec<_>>();
+  let app_matches = clap_app! {single_pyo3 =>
+    (version: "0.1")
+    (author: "Will Crichton <crichton.will@gmail.com>")
+    (about: "Builds a single Rust file as a Python module via pyo3")
+    (@setting SubcommandsNegateReqs)
+    (@arg verbose: -v --verbose +global)
+    (@arg release: --release +global)
+    (@arg pyo3: --pyo3 +takes_value +global "Pyo3 version. Defaults to the version the file was last built with. Use \"latest\" for the newest release, or \"github\" to get latest from main branch.")
+    (@arg pyo3_git: --("pyo3-git") +takes_value +global conflicts_with[pyo3 pyo3_path] "Git repository to take pyo3 from, e.g. a fork")
+    (@arg pyo3_rev: --("pyo3-rev") +takes_value +global requires[pyo3_git] "Commit of --pyo3-git to build against, instead of its default branch")
+    (@arg pyo3_path: --("pyo3-path") +takes_value +global conflicts_with[pyo3] "Local pyo3 checkout to build against")
+    (@arg pyo3_features: --("pyo3-features") +takes_value +multiple +use_delimiter +require_delimiter +global "Comma-separated pyo3 features to enable, e.g. num-bigint,chrono")
+    (@arg isolated_target: --("isolated-target") +global "Use a target directory private to this file instead of the shared one")
+    (@arg python: --python +takes_value +global "Python interpreter to build against. Defaults to PYO3_PYTHON, then the active virtualenv or conda environment.")
+    (@arg plain_suffix: --("plain-suffix") +global "Name the output {module}.so ({module}.pyd on Windows) instead of using the interpreter's EXT_SUFFIX")
+    (@arg out: -o --("out-dir") +takes_value +global "Directory or file path to write the output to. Defaults to the current directory.")
+    (@arg next_to_input: --("next-to-input") +global conflicts_with[out] "Write the output next to the input file. Set CARGO_SINGLE_PYO3_NEXT_TO_INPUT to make this the default.")
+    (@arg abi3: --abi3 +takes_value +global "Build against the stable ABI for this minimum Python version, e.g. 3.8, so that one module works with every later version")
+    (@arg auto_module: --("auto-module") +global "If the file has no #[pymodule], generate one registering every #[pyfunction] and #[pyclass]")
+    (@arg stubs: --stubs +global "Also write a .pyi type stub for the module")
+    (@arg lock: --lock +global "Keep the Cargo.lock of each file next to it, e.g. foo.rs.lock, and build with it")
+    (@arg locked: --locked +global "Like --lock, but fail if the lockfile is missing or would change")
+    (@arg target: --target +takes_value +global "Target triple to build for, e.g. aarch64-unknown-linux-gnu")
+    (@arg cross_lib_dir: --("cross-lib-dir") +takes_value +global "Directory holding the target Python's _sysconfigdata when cross compiling. Defaults to assuming the version of --python.")
+    (@arg manylinux: --manylinux +takes_value +global "Check that the module only uses libraries and symbol versions this manylinux policy allows, e.g. 2014, and tag wheels with it")
+    (@arg offline: --offline +global "Build without accessing the network, as with cargo --offline")
+    (@arg frozen: --frozen +global "Build without accessing the network or updating the lockfile, as with cargo --frozen")
+    (@arg watch: -w --watch +global "Rebuild whenever the input file or a file it includes changes")
+    (@arg INPUT: +required +multiple "Input files. Quoted glob patterns are expanded.")
+    (@arg CARGO_ARGS: +multiple +last "Arguments after -- are passed on to cargo build, e.g. -- --features foo -j 4")
+    (@arg install: --install "Install the module into the target interpreter's site-packages, like the develop subcommand")
+    (@subcommand develop =>
+      (about: "Installs each module into the site-packages of the target interpreter, which must be in a virtualenv or conda environment, replacing any previous install")
+      (@arg INPUT: +required +multiple "Input files. Quoted glob patterns are expanded.")
+      (@arg CARGO_ARGS: +multiple +last "Arguments after -- are passed on to cargo build")
+    )
+    (@subcommand vendor =>
+      (about: "Vendors the dependencies of each input into its generated project, so that later builds work offline")
+      (@arg INPUT: +required +multiple "Input files. Quoted glob patterns are expanded.")
+    )
+    (@subcommand wheel =>
+      (about: "Packages each input as a wheel that pip can install. Builds in release mode.")
+      (@arg INPUT: +required +multiple "Input files. Quoted glob patterns are expanded.")
+      (@arg CARGO_ARGS: +multiple +last "Arguments after -- are passed on to cargo build")
+    )
+  }
+  .get_matches_from(&clap_args);
+
+  // Options marked global are propagated down, so when a subcommand is given
+  // its matches hold everything.
+  let (mode, matches) = match app_matches.subcommand() {
+    ("wheel", Some(matches)) => (Mode::Wheel, matches),
+    ("develop", Some(matches)) => (Mode::Develop, matches),
+    ("vendor", Some(matches)) => (Mode::Module, matches),
+    _ if app_matches.is_present("install") => (Mode::Develop, &app_matches),
+    _ => (Mode::Module, &app_matches),
+  };
+
+  let verbose = matches.is_present("verbose");
+  let mut inputs = Vec::new();
+  for pattern in matches.values_of("INPUT").unwrap() {
+    if pattern.contains(['*', '?', '[']) {
+      let paths = glob::glob(pattern)?.collect::<Result<Vec<_>, _>>()?;
+      if paths.is_empty() {
+        bail!("No files match {}", pattern);
+      }
+      inputs.extend(paths);
+    } else {
+      inputs.push(PathBuf::from(pattern));
+    }
+  }
+  // The same file can be named twice, e.g. as `foo.rs` and `./foo.rs`, or by
+  // a glob and explicitly. Files that cannot be resolved are left for the
+  // build to report.
+  let mut seen = HashSet::new();
+  inputs.retain(|input| seen.insert(input.canonicalize().unwrap_or_else(|_| input.clone())));
+  let inputs = inputs.iter().map(PathBuf::as_path).collect::<Vec<_>>();
+
+  if let Some(out) = matches.value_of("out") {
+    if inputs.len() > 1 && Path::new(out).extension().is_some() && !Path::new(out).is_dir() {
+      bail!("-o must be a directory when building several modules");
+    }
+  }
+
+  let python = python::find_interpreter(matches.value_of("python"));
+  if verbose {
+    println!("{}", python.display());
+  }
+
+  let opts = BuildOptions {
+    mode,
+    verbose,
+    release: matches.is_present("release") || mode == Mode::Wheel,
+    pyo3: matches.value_of("pyo3"),
+    pyo3_git: matches.value_of("pyo3_git"),
+    pyo3_rev: matches.value_of("pyo3_rev"),
+    pyo3_path: match matches.value_of("pyo3_path") {
+      Some(path) => Some(env::current_dir()?.join(path)),
+      None => None,
+    },
+    pyo3_features: matches
+      .values_of("pyo3_features")
+      .map_or_else(Vec::new, Iterator::collect),
+    python,
+    isolated_target: matches.is_present("isolated_target"),
+    plain_suffix: matches.is_present("plain_suffix"),
+    out: matches.value_of("out"),
+    next_to_input: matches.is_present("next_to_input")
+      || (!matches.is_present("out") && env::var_os("CARGO_SINGLE_PYO3_NEXT_TO_INPUT").is_some()),
+    abi3: matches
+      .value_of("abi3")
+      .map(python::parse_version)
+      .transpose()?,
+    auto_module: matches.is_present("auto_module"),
+    stubs: matches.is_present("stubs"),
+    lock: matches.is_present("lock") || matches.is_present("locked"),
+    locked: matches.is_present("locked"),
+    offline: matches.is_present("offline"),
+    frozen: matches.is_present("frozen"),
+    vendor: app_matches.subcommand_name() == Some("vendor"),
+    // A target from cargo's environment needs the same setup as `--target`.
+    target: match matches.value_of("target") {
+      Some(triple) => Some(Target::new(triple)?),
+      None => match env::var("CARGO_BUILD_TARGET") {
+        Ok(triple) if !triple.is_empty() => Some(Target::new(&triple)?),
+        _ => None,
+      },
+    },
+    cross_lib_dir: match matches.value_of("cross_lib_dir") {
+      Some(dir) => Some(env::current_dir()?.join(dir)),
+      None => None,
+    },
+    manylinux: matches
+      .value_of("manylinux")
+      .map(manylinux::Policy::new)
+      .transpose()?,
+    cargo_args: matches
+      .values_of("CARGO_ARGS")
+      .map_or_else(Vec::new, Iterator::collect),
+  };
+
+  if cargo_arg(&opts.cargo_args, "target").is_some() {
+    bail!("--target must be given before `--`, so that the module is named and configured for the target");
+  }
+  // The built modules are found through cargo's JSON messages.
+  if cargo_arg(&opts.cargo_args, "message-format").is_some() {
+    bail!("--message-format cannot be passed to cargo, since the built modules are found from its JSON output");
+  }
+
+  if let Some(policy) = opts.manylinux {
+    let is_linux = match &opts.target {
+      Some(target) => target.is_linux(),
+      None => cfg!(target_os = "linux"),
+    };
+    if !is_linux {
+      bail!("{} only applies to Linux builds", policy.name);
+    }
+  }
+
+  if let Some(target) = opts.target.as_ref().filter(|target| target.is_cross) {
+    if opts.mode == Mode::Develop {
+      bail!(
+        "Cannot install a module built for {} into the host interpreter",
+        target.triple
+      );
+    }
+  }
+
+  if matches.is_present("watch") {
+    watch::watch(&inputs, || build_all(&inputs, &opts))
+  } else {
+    build_all(&inputs, &opts).map(|_| ())
+  }
+}
+
 fn main() {
-  run().unwrap();
+  if let Err(err) = run() {
+    eprintln!("error: {:?}", err);
+    std::process::exit(1);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn compatible_versions() {
+    assert_eq!(compatible_version("0.22"), "0.22");
+    assert_eq!(compatible_version("0.22.6"), "0.22");
+    assert_eq!(compatible_version("^0.21"), "0.21");
+    assert_eq!(compatible_version("= 1.2.3"), "1");
+    assert_eq!(compatible_version("*"), "*");
+  }
+
+  #[test]
+  fn cargo_args() {
+    let args = ["--profile", "fast", "--features=simd", "--profile=small"];
+    assert_eq!(cargo_arg(&args, "profile"), Some("small"));
+    assert_eq!(cargo_arg(&args, "features"), Some("simd"));
+    assert_eq!(cargo_arg(&args, "feature"), None);
+    assert_eq!(cargo_arg(&["--target"], "target"), None);
+  }
 }
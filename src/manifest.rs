@@ -0,0 +1,562 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+#[derive(Serialize)]
+pub struct CargoConfig {
+  pub package: CargoPackage,
+  pub lib: CargoLib,
+  pub dependencies: HashMap<String, CargoDependency>,
+}
+
+#[derive(Serialize)]
+pub struct CargoPackage {
+  pub name: String,
+  pub version: String,
+  pub edition: String,
+}
+
+#[derive(Serialize)]
+pub struct CargoLib {
+  pub name: String,
+  #[serde(rename = "crate-type")]
+  pub crate_type: Vec<String>,
+}
+
+/// Root manifest of a workspace generated to build several inputs at once.
+#[derive(Serialize)]
+pub struct CargoWorkspaceConfig {
+  pub workspace: CargoWorkspace,
+  /// The `ROOT_SECTIONS` of every input's header.
+  #[serde(flatten)]
+  pub sections: toml::value::Table,
+}
+
+#[derive(Serialize)]
+pub struct CargoWorkspace {
+  pub members: Vec<String>,
+  pub resolver: String,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct CargoDependency {
+  pub version: Option<String>,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub features: Vec<String>,
+  #[serde(alias = "default_features")]
+  pub default_features: Option<bool>,
+  pub optional: Option<bool>,
+  pub public: Option<bool>,
+  pub package: Option<String>,
+  pub registry: Option<String>,
+  pub registry_index: Option<String>,
+  pub git: Option<String>,
+  pub branch: Option<String>,
+  pub tag: Option<String>,
+  pub rev: Option<String>,
+  pub path: Option<String>,
+  /// Artifact dependencies, e.g. `artifact = "bin"` with `target` and `lib`.
+  pub artifact: Option<toml::Value>,
+  pub target: Option<String>,
+  pub lib: Option<bool>,
+}
+
+impl CargoDependency {
+  /// Whether the dependency says where to get the crate from.
+  pub fn has_source(&self) -> bool {
+    self.version.is_some() || self.git.is_some() || self.path.is_some()
+  }
+}
+
+/// Top-level tables of a Cargo manifest, besides `[dependencies]`.
+const SECTIONS: &[&str] = &[
+  "package",
+  "lib",
+  "features",
+  "profile",
+  "dev-dependencies",
+  "build-dependencies",
+  "target",
+  "patch",
+  "replace",
+  "badges",
+];
+
+/// Sections that cargo only honours in the workspace root, and ignores or
+/// warns about in member manifests.
+pub const ROOT_SECTIONS: &[&str] = &["profile", "patch", "replace"];
+
+/// Keys of the generated manifest that a header may not override, since the
+/// build relies on them to locate the compiled module.
+const RESERVED: &[(&str, &str)] = &[("package", "name"), ("lib", "name"), ("lib", "crate-type")];
+
+/// Manifest fragment embedded at the top of the input file.
+#[derive(Default)]
+pub struct Header {
+  pub dependencies: HashMap<String, CargoDependency>,
+  /// Every other manifest section, e.g. `[package]` or `[profile.release]`.
+  pub sections: toml::value::Table,
+}
+
+/// Which of the supported header syntaxes a file uses.
+#[derive(PartialEq, Eq)]
+enum HeaderKind {
+  /// Leading `// ` comment lines whose bare keys are dependencies.
+  Dependencies,
+  /// A complete manifest, either in a `//! ```cargo` doc-comment block as
+  /// used by cargo-script and rust-script, or in `---cargo` frontmatter as
+  /// described by RFC 3424.
+  Manifest,
+}
+
+/// The lines of a header, and the 0-based index of the first one in the file.
+struct HeaderLines<'a> {
+  kind: HeaderKind,
+  start: usize,
+  lines: Vec<&'a str>,
+  /// For frontmatter, the range of lines to blank out of the source.
+  frontmatter: Option<(usize, usize)>,
+}
+
+fn find_header<'a>(input: &Path, src: &'a str) -> Result<HeaderLines<'a>> {
+  let lines = src.lines().collect::<Vec<_>>();
+  let mut i = 0;
+  if lines
+    .first()
+    .is_some_and(|line| line.starts_with("#!") && !line.starts_with("#!["))
+  {
+    i += 1;
+  }
+
+  let mut first = i;
+  while first < lines.len() && lines[first].trim().is_empty() {
+    first += 1;
+  }
+
+  if let Some(line) = lines.get(first).filter(|line| line.starts_with("---")) {
+    let fence = line.chars().take_while(|c| *c == '-').count();
+    let infostring = line[fence..].trim();
+    if !infostring.is_empty() && infostring != "cargo" {
+      bail!(
+        "{}:{}: unsupported frontmatter `{}`, expected `cargo`",
+        input.display(),
+        first + 1,
+        infostring
+      );
+    }
+
+    let closing = "-".repeat(fence);
+    let end = lines[first + 1..]
+      .iter()
+      .position(|line| line.trim_end() == closing)
+      .map(|j| first + 1 + j)
+      .with_context(|| {
+        format!(
+          "{}:{}: unterminated frontmatter",
+          input.display(),
+          first + 1
+        )
+      })?;
+
+    return Ok(HeaderLines {
+      kind: HeaderKind::Manifest,
+      start: first + 1,
+      lines: lines[first + 1..end].to_vec(),
+      frontmatter: Some((first, end)),
+    });
+  }
+
+  if lines.get(first).is_some_and(|line| line.starts_with("//!")) {
+    let doc = lines[first..]
+      .iter()
+      .take_while(|line| line.starts_with("//!"))
+      .map(|line| {
+        let line = &line[3..];
+        line.strip_prefix(' ').unwrap_or(line)
+      })
+      .collect::<Vec<_>>();
+
+    if let Some(open) = doc.iter().position(|line| line.trim() == "```cargo") {
+      let close = doc[open + 1..]
+        .iter()
+        .position(|line| line.trim() == "```")
+        .map(|j| open + 1 + j)
+        .with_context(|| {
+          format!(
+            "{}:{}: unterminated ```cargo block",
+            input.display(),
+            first + open + 1
+          )
+        })?;
+
+      return Ok(HeaderLines {
+        kind: HeaderKind::Manifest,
+        start: first + open + 1,
+        lines: doc[open + 1..close].to_vec(),
+        frontmatter: None,
+      });
+    }
+  }
+
+  Ok(HeaderLines {
+    kind: HeaderKind::Dependencies,
+    start: i,
+    lines: lines[i..]
+      .iter()
+      .take_while(|line| line.starts_with("// "))
+      .map(|line| &line[3..])
+      .collect(),
+    frontmatter: None,
+  })
+}
+
+/// Parses the manifest fragment at the top of `src`, returning it along with
+/// the source that should be compiled.
+///
+/// Three header syntaxes are recognised, checked in this order:
+/// * `---cargo` frontmatter, which is replaced with blank lines in the
+///   returned source since rustc does not accept it;
+/// * a `//! ```cargo` block inside the leading inner doc comment;
+/// * leading `// ` comment lines, where bare keys are dependencies and any
+///   other manifest section may follow, e.g. `// [profile.release]`.
+pub fn parse_header(input: &Path, src: &str) -> Result<(Header, String)> {
+  let header = find_header(input, src)?;
+  let at = |i: usize| format!("{}:{}", input.display(), header.start + i + 1);
+
+  let mut table: toml::value::Table = toml::from_str(&header.lines.join("\n")).map_err(|err| {
+    // The position toml reports is relative to the fragment, so it is
+    // replaced with one in the file, counting the stripped comment prefix.
+    let message = err.to_string();
+    let message = message
+      .rfind(" at line ")
+      .map_or(&*message, |end| &message[..end]);
+    match err.line_col() {
+      Some((line, col)) => {
+        let prefix = src.lines().nth(header.start + line).map_or(0, |full| {
+          full.len() - header.lines.get(line).map_or(full.len(), |line| line.len())
+        });
+        anyhow!(
+          "{}:{}: invalid manifest header: {}",
+          at(line),
+          prefix + col + 1,
+          message
+        )
+      }
+      None => anyhow!("{}: invalid manifest header: {}", at(0), message),
+    }
+  })?;
+
+  let mut deps = match table.remove("dependencies") {
+    Some(toml::Value::Table(deps)) => deps,
+    Some(other) => bail!(
+      "{}: `dependencies` must be a table, found {}",
+      at(find_key(&header.lines, "dependencies")),
+      other.type_str()
+    ),
+    None => toml::value::Table::new(),
+  };
+
+  // Bare keys in a `// ` header are dependencies, unless they name one of the
+  // manifest's own sections.
+  if header.kind == HeaderKind::Dependencies {
+    let bare = table
+      .keys()
+      .filter(|key| !SECTIONS.contains(&key.as_str()))
+      .cloned()
+      .collect::<Vec<_>>();
+    for name in bare {
+      let value = table.remove(&name).unwrap();
+      deps.insert(name, value);
+    }
+  }
+
+  for (section, key) in RESERVED {
+    if let Some(toml::Value::Table(table)) = table.get(*section) {
+      if table.contains_key(*key) {
+        bail!(
+          "{}: `{}.{}` is derived from the file name and cannot be overridden",
+          at(find_key(&header.lines, key)),
+          section,
+          key
+        );
+      }
+    }
+  }
+  let sections = table;
+
+  let mut dependencies = HashMap::new();
+  for (name, value) in deps {
+    let line = at(find_key(&header.lines, &name));
+    let dep = match value {
+      toml::Value::String(version) => CargoDependency {
+        version: Some(version),
+        ..Default::default()
+      },
+      value @ toml::Value::Table(_) => value
+        .try_into()
+        .map_err(|err| anyhow!("{}: invalid dependency `{}`: {}", line, name, err))?,
+      other => bail!(
+        "{}: dependency `{}` must be a version string or a table, found {}",
+        line,
+        name,
+        other.type_str()
+      ),
+    };
+    dependencies.insert(name, dep);
+  }
+
+  let src = match header.frontmatter {
+    Some((open, close)) => src
+      .lines()
+      .enumerate()
+      .map(|(i, line)| {
+        if (open..=close).contains(&i) {
+          ""
+        } else {
+          line
+        }
+      })
+      .collect::<Vec<_>>()
+      .join("\n"),
+    None => src.to_owned(),
+  };
+
+  Ok((
+    Header {
+      dependencies,
+      sections,
+    },
+    src,
+  ))
+}
+
+/// Index of the first header line that defines `key`: either a `key = ...`
+/// or `key.sub = ...` line, or a table header such as `[dependencies.key]`.
+fn find_key(lines: &[&str], key: &str) -> usize {
+  let is_key = |part: &str| part.trim().trim_matches(['"', '\'']) == key;
+  lines
+    .iter()
+    .position(|line| {
+      let line = line.trim();
+      match line.strip_prefix('[') {
+        Some(header) => header
+          .trim_start_matches('[')
+          .split(']')
+          .next()
+          .is_some_and(|path| path.split('.').any(is_key)),
+        None => line
+          .split('=')
+          .next()
+          .filter(|_| line.contains('='))
+          .and_then(|lhs| lhs.split('.').next())
+          .is_some_and(is_key),
+      }
+    })
+    .unwrap_or(0)
+}
+
+/// Recursively merges `overrides` into `base`. Tables are merged key by key;
+/// any other value in `overrides` replaces the one in `base`.
+pub fn merge(base: &mut toml::value::Table, overrides: toml::value::Table) {
+  for (key, value) in overrides {
+    match (base.get_mut(&key), value) {
+      (Some(toml::Value::Table(base)), toml::Value::Table(overrides)) => merge(base, overrides),
+      (_, value) => {
+        base.insert(key, value);
+      }
+    }
+  }
+}
+
+/// Merges `overrides` into `base` like `merge`, but fails with the dotted
+/// path of the first key that both set to different values.
+pub fn merge_disjoint(
+  base: &mut toml::value::Table,
+  overrides: toml::value::Table,
+) -> Result<(), String> {
+  for (key, value) in overrides {
+    match (base.get_mut(&key), value) {
+      (Some(toml::Value::Table(base)), toml::Value::Table(overrides)) => {
+        merge_disjoint(base, overrides).map_err(|path| format!("{}.{}", key, path))?
+      }
+      (Some(existing), value) if *existing != value => return Err(key),
+      (_, value) => {
+        base.insert(key, value);
+      }
+    }
+  }
+  Ok(())
+}
+
+/// The version of `dependency` that `package` was resolved against in the
+/// `Cargo.lock` at `lock`.
+pub fn locked_version(lock: &Path, package: &str, dependency: &str) -> Option<String> {
+  let lock = fs::read_to_string(lock).ok()?.parse::<toml::Value>().ok()?;
+  let packages = lock.get("package")?.as_array()?;
+  let is_named =
+    |entry: &toml::Value, name: &str| entry.get("name").and_then(toml::Value::as_str) == Some(name);
+  let version = |entry: &toml::Value| {
+    entry
+      .get("version")
+      .and_then(toml::Value::as_str)
+      .map(str::to_owned)
+  };
+
+  // Dependencies are listed by name, followed by the version if several
+  // versions of the crate are in the graph.
+  let entry = packages.iter().find(|entry| is_named(entry, package))?;
+  let spec = entry
+    .get("dependencies")?
+    .as_array()?
+    .iter()
+    .filter_map(toml::Value::as_str)
+    .find(|spec| spec.split(' ').next() == Some(dependency))?;
+  match spec.split(' ').nth(1) {
+    Some(locked) => Some(locked.to_owned()),
+    None => packages
+      .iter()
+      .find(|entry| is_named(entry, dependency))
+      .and_then(version),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn error(src: &str) -> String {
+    format!("{}", parse_header(Path::new("t.rs"), src).err().unwrap())
+  }
+
+  #[test]
+  fn find_key_matches_whole_keys() {
+    let lines = ["serde_json = \"1\"", "serde = { versoin = \"1\" }"];
+    assert_eq!(find_key(&lines, "serde"), 1);
+    assert_eq!(find_key(&lines, "serde_json"), 0);
+    assert_eq!(find_key(&["rand.version = \"0.8\""], "rand"), 0);
+    assert_eq!(find_key(&["\"rand\" = \"0.8\""], "rand"), 0);
+  }
+
+  #[test]
+  fn find_key_matches_table_headers() {
+    let lines = [
+      "[package]",
+      "edition = \"2021\"",
+      "[dependencies.serde]",
+      "version = \"1\"",
+    ];
+    assert_eq!(find_key(&lines, "serde"), 2);
+    assert_eq!(find_key(&lines, "package"), 0);
+    assert_eq!(find_key(&["[lib]", "name = \"x\""], "name"), 1);
+  }
+
+  #[test]
+  fn dependency_errors_point_at_their_line() {
+    assert!(
+      error("// serde_json = \"1\"\n// serde = { versoin = \"1\" }\n").starts_with("t.rs:2:")
+    );
+    assert!(error(
+      "---cargo\n[package]\nedition = \"2021\"\n\n[dependencies.serde]\nversoin = \"1\"\n---\n"
+    )
+    .starts_with("t.rs:5:"));
+  }
+
+  #[test]
+  fn dependencies_accept_every_cargo_key() {
+    let (header, _) = parse_header(
+      Path::new("t.rs"),
+      "// serde = { version = \"1\", registry = \"internal\", default_features = false }\n\
+       // bin = { path = \"bin\", artifact = \"bin\", target = \"x86_64-unknown-linux-gnu\", lib = true }\n",
+    )
+    .unwrap();
+    let serde = &header.dependencies["serde"];
+    assert_eq!(serde.registry.as_deref(), Some("internal"));
+    assert_eq!(serde.default_features, Some(false));
+    assert_eq!(header.dependencies["bin"].lib, Some(true));
+  }
+
+  #[test]
+  fn merge_is_recursive() {
+    let mut base: toml::value::Table =
+      toml::from_str("[profile.release]\nlto = false\nopt-level = 3\n").unwrap();
+    let overrides: toml::value::Table = toml::from_str("[profile.release]\nlto = true\n").unwrap();
+    merge(&mut base, overrides);
+    let release = &base["profile"]["release"];
+    assert_eq!(release["lto"].as_bool(), Some(true));
+    assert_eq!(release["opt-level"].as_integer(), Some(3));
+  }
+
+  #[test]
+  fn locked_versions() {
+    let lock = std::env::temp_dir().join(format!("locked-{}.lock", std::process::id()));
+    fs::write(
+      &lock,
+      r#"
+[[package]]
+name = "foo"
+version = "0.1.0"
+dependencies = ["pyo3 0.22.6", "serde"]
+
+[[package]]
+name = "pyo3"
+version = "0.20.3"
+
+[[package]]
+name = "pyo3"
+version = "0.22.6"
+
+[[package]]
+name = "serde"
+version = "1.0.210"
+"#,
+    )
+    .unwrap();
+    assert_eq!(
+      locked_version(&lock, "foo", "pyo3").as_deref(),
+      Some("0.22.6")
+    );
+    assert_eq!(
+      locked_version(&lock, "foo", "serde").as_deref(),
+      Some("1.0.210")
+    );
+    assert_eq!(locked_version(&lock, "foo", "rand"), None);
+    assert_eq!(locked_version(&lock, "bar", "pyo3"), None);
+    fs::remove_file(&lock).unwrap();
+  }
+
+  #[test]
+  fn merge_disjoint_reports_conflicts() {
+    let mut base: toml::value::Table =
+      toml::from_str("[profile.release]\nlto = true\nopt-level = 3\n").unwrap();
+    let same: toml::value::Table =
+      toml::from_str("[profile.release]\nopt-level = 3\ndebug = true\n").unwrap();
+    assert_eq!(merge_disjoint(&mut base, same), Ok(()));
+    assert_eq!(base["profile"]["release"]["debug"].as_bool(), Some(true));
+
+    let conflicting: toml::value::Table =
+      toml::from_str("[profile.release]\nlto = false\n").unwrap();
+    assert_eq!(
+      merge_disjoint(&mut base, conflicting),
+      Err("profile.release.lto".into())
+    );
+  }
+
+  #[test]
+  fn toml_errors_point_into_the_file() {
+    assert_eq!(
+      error("// [dependencies]\n// serde = 1 2\n"),
+      "t.rs:2:14: invalid manifest header: expected newline, found an identifier"
+    );
+    assert_eq!(
+      error("//! Docs.\n//!\n//! ```cargo\n//! [package]\n//!edition = 20 21\n//! ```\n"),
+      "t.rs:5:17: invalid manifest header: expected newline, found an identifier"
+    );
+    assert_eq!(
+      error("---cargo\n[package]\nedition = 20 21\n---\n"),
+      "t.rs:3:14: invalid manifest header: expected newline, found an identifier"
+    );
+  }
+}
@@ -0,0 +1,381 @@
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// A manylinux policy: the libraries a module may link against and the
+/// newest symbol versions it may require from them, as in auditwheel.
+pub struct Policy {
+  pub name: &'static str,
+  /// The PEP 600 name of the policy, e.g. `manylinux_2_17`.
+  alias: &'static str,
+  libraries: &'static [&'static str],
+  /// The newest allowed version of each versioned symbol namespace.
+  versions: &'static [(&'static str, &'static str)],
+}
+
+const MANYLINUX2014: Policy = Policy {
+  name: "manylinux2014",
+  alias: "manylinux_2_17",
+  libraries: &[
+    "libgcc_s.so.1",
+    "libstdc++.so.6",
+    "libm.so.6",
+    "libdl.so.2",
+    "librt.so.1",
+    "libc.so.6",
+    "libnsl.so.1",
+    "libutil.so.1",
+    "libpthread.so.0",
+    "libresolv.so.2",
+    "libX11.so.6",
+    "libXext.so.6",
+    "libXrender.so.1",
+    "libICE.so.6",
+    "libSM.so.6",
+    "libGL.so.1",
+    "libgobject-2.0.so.0",
+    "libgthread-2.0.so.0",
+    "libglib-2.0.so.0",
+  ],
+  versions: &[
+    ("GLIBC", "2.17"),
+    ("GLIBCXX", "3.4.19"),
+    ("CXXABI", "1.3.7"),
+    ("GCC", "4.8.0"),
+  ],
+};
+
+impl Policy {
+  /// The policy named by `--manylinux`, e.g. `2014` or `2_17`.
+  pub fn new(name: &str) -> Result<&'static Policy> {
+    match name {
+      "2014" | "2_17" => Ok(&MANYLINUX2014),
+      _ => bail!("Unsupported manylinux policy `{}`, expected 2014", name),
+    }
+  }
+
+  /// The wheel platform tag for a Linux `platform` tag such as
+  /// `linux_x86_64`, listing both names of the policy.
+  pub fn platform_tag(&self, platform: &str) -> Result<String> {
+    let Some(arch) = platform.strip_prefix("linux_") else {
+      bail!(
+        "{} wheels must be built for Linux, not {}",
+        self.name,
+        platform
+      );
+    };
+    Ok(format!("{}_{}.{}_{}", self.alias, arch, self.name, arch))
+  }
+
+  /// Checks that the shared library at `path` only links against libraries
+  /// the policy allows, and only requires symbol versions it allows.
+  pub fn audit(&self, path: &Path) -> Result<()> {
+    let data = fs::read(path).with_context(|| format!("Could not read {}", path.display()))?;
+    let deps = Dependencies::read(&data)
+      .with_context(|| format!("Could not read ELF dynamic section of {}", path.display()))?;
+
+    let mut violations = Vec::new();
+    for library in &deps.needed {
+      if !self.libraries.contains(&library.as_str()) && !library.starts_with("ld-linux") {
+        violations.push(format!("links against {}, which is not allowed", library));
+      }
+    }
+    for ((library, version), symbols) in &deps.versions {
+      if !self.allows(version) {
+        let mut shown = symbols.iter().take(5).cloned().collect::<Vec<_>>();
+        if symbols.len() > shown.len() {
+          shown.push("...".into());
+        }
+        violations.push(if shown.is_empty() {
+          format!("requires {} from {}", version, library)
+        } else {
+          format!(
+            "requires {} from {} for {}",
+            version,
+            library,
+            shown.join(", ")
+          )
+        });
+      }
+    }
+
+    if !violations.is_empty() {
+      bail!(
+        "{} is not {} compliant:\n  {}\nBuild on a system with an older glibc, such as the {} container image",
+        path.display(),
+        self.name,
+        violations.join("\n  "),
+        self.name
+      );
+    }
+    Ok(())
+  }
+
+  /// Whether a symbol version such as `GLIBC_2.14` is allowed.
+  fn allows(&self, version: &str) -> bool {
+    let Some((namespace, number)) = version.split_once('_') else {
+      return true;
+    };
+    let Some((_, max)) = self.versions.iter().find(|(name, _)| *name == namespace) else {
+      return true;
+    };
+    // Unnumbered versions such as `CXXABI_TM_1` predate every policy, except
+    // for `GLIBC_PRIVATE`, which is never stable.
+    match parse_version(number) {
+      Some(number) => number <= parse_version(max).unwrap_or_default(),
+      None => number != "PRIVATE",
+    }
+  }
+}
+
+fn parse_version(version: &str) -> Option<Vec<u32>> {
+  version.split('.').map(|part| part.parse().ok()).collect()
+}
+
+/// What a shared library needs from the dynamic linker: its `DT_NEEDED`
+/// libraries, and the symbol versions it requires from each, with the
+/// undefined symbols that require them.
+struct Dependencies {
+  needed: Vec<String>,
+  versions: BTreeMap<(String, String), Vec<String>>,
+}
+
+const SHT_DYNSYM: u32 = 11;
+const SHT_DYNAMIC: u32 = 6;
+const SHT_GNU_VERNEED: u32 = 0x6ffffffe;
+const SHT_GNU_VERSYM: u32 = 0x6fffffff;
+const DT_NEEDED: u64 = 1;
+
+impl Dependencies {
+  fn read(data: &[u8]) -> Result<Dependencies> {
+    let elf = Elf::new(data)?;
+    let sections = elf.sections()?;
+    let find = |kind: u32| sections.iter().find(|section| section.kind == kind);
+
+    let mut needed = Vec::new();
+    if let Some(dynamic) = find(SHT_DYNAMIC) {
+      let strings = elf.section(&sections, dynamic.link)?;
+      let size = if elf.is_64 { 16 } else { 8 };
+      for i in 0..dynamic.size / size {
+        let entry = dynamic.offset + i * size;
+        if elf.word(entry)? == DT_NEEDED {
+          needed.push(elf.string(strings, elf.word(entry + size / 2)?)?);
+        }
+      }
+    }
+
+    // Version indices in .gnu.version refer to the `vna_other` of the
+    // entries in .gnu.version_r.
+    let mut index_names = BTreeMap::new();
+    if let Some(verneed) = find(SHT_GNU_VERNEED) {
+      let strings = elf.section(&sections, verneed.link)?;
+      let mut offset = verneed.offset;
+      for _ in 0..verneed.info {
+        let file = elf.string(strings, u64::from(elf.u32(offset + 4)?))?;
+        let mut aux = offset + elf.u32(offset + 8)? as usize;
+        for _ in 0..elf.u16(offset + 2)? {
+          let index = elf.u16(aux + 6)? & 0x7fff;
+          let name = elf.string(strings, u64::from(elf.u32(aux + 8)?))?;
+          index_names.insert(index, (file.clone(), name));
+          aux += elf.u32(aux + 12)? as usize;
+        }
+        match elf.u32(offset + 12)? {
+          0 => break,
+          next => offset += next as usize,
+        }
+      }
+    }
+
+    let mut versions = BTreeMap::new();
+    for (file, name) in index_names.values() {
+      versions.insert((file.clone(), name.clone()), Vec::new());
+    }
+    if let (Some(dynsym), Some(versym)) = (find(SHT_DYNSYM), find(SHT_GNU_VERSYM)) {
+      let strings = elf.section(&sections, dynsym.link)?;
+      let size = if elf.is_64 { 24 } else { 16 };
+      for i in 0..dynsym.size / size {
+        let symbol = dynsym.offset + i * size;
+        let shndx = elf.u16(symbol + if elf.is_64 { 6 } else { 14 })?;
+        let index = elf.u16(versym.offset + i * 2)? & 0x7fff;
+        if shndx != 0 {
+          continue;
+        }
+        if let Some(key) = index_names.get(&index) {
+          let name = elf.string(strings, u64::from(elf.u32(symbol)?))?;
+          if let Some(symbols) = versions.get_mut(key) {
+            symbols.push(name);
+          }
+        }
+      }
+    }
+
+    Ok(Dependencies { needed, versions })
+  }
+}
+
+struct Section {
+  kind: u32,
+  offset: usize,
+  size: usize,
+  link: u32,
+  info: u32,
+}
+
+/// Just enough of an ELF reader to walk the section headers.
+struct Elf<'a> {
+  data: &'a [u8],
+  is_64: bool,
+  little_endian: bool,
+}
+
+impl<'a> Elf<'a> {
+  fn new(data: &'a [u8]) -> Result<Elf<'a>> {
+    if !data.starts_with(b"\x7fELF") || data.len() < 16 {
+      bail!("not an ELF file");
+    }
+    Ok(Elf {
+      data,
+      is_64: data[4] == 2,
+      little_endian: data[5] == 1,
+    })
+  }
+
+  fn bytes(&self, offset: usize, len: usize) -> Result<&'a [u8]> {
+    offset
+      .checked_add(len)
+      .and_then(|end| self.data.get(offset..end))
+      .context("truncated ELF file")
+  }
+
+  fn uint(&self, offset: usize, len: usize) -> Result<u64> {
+    let bytes = self.bytes(offset, len)?;
+    let fold = |value: u64, byte: &u8| value << 8 | u64::from(*byte);
+    Ok(if self.little_endian {
+      bytes.iter().rev().fold(0, fold)
+    } else {
+      bytes.iter().fold(0, fold)
+    })
+  }
+
+  fn u16(&self, offset: usize) -> Result<u16> {
+    Ok(self.uint(offset, 2)? as u16)
+  }
+
+  fn u32(&self, offset: usize) -> Result<u32> {
+    Ok(self.uint(offset, 4)? as u32)
+  }
+
+  /// A word the size of an address, as in `Elf64_Xword` or `Elf32_Word`.
+  fn word(&self, offset: usize) -> Result<u64> {
+    self.uint(offset, if self.is_64 { 8 } else { 4 })
+  }
+
+  fn sections(&self) -> Result<Vec<Section>> {
+    let (table, entry_size, count) = if self.is_64 {
+      (self.word(0x28)?, self.u16(0x3a)?, self.u16(0x3c)?)
+    } else {
+      (self.word(0x20)?, self.u16(0x2e)?, self.u16(0x30)?)
+    };
+
+    (0..usize::from(count))
+      .map(|i| {
+        let header = table as usize + i * usize::from(entry_size);
+        Ok(if self.is_64 {
+          Section {
+            kind: self.u32(header + 4)?,
+            offset: self.word(header + 24)? as usize,
+            size: self.word(header + 32)? as usize,
+            link: self.u32(header + 40)?,
+            info: self.u32(header + 44)?,
+          }
+        } else {
+          Section {
+            kind: self.u32(header + 4)?,
+            offset: self.word(header + 16)? as usize,
+            size: self.word(header + 20)? as usize,
+            link: self.u32(header + 24)?,
+            info: self.u32(header + 28)?,
+          }
+        })
+      })
+      .collect()
+  }
+
+  /// The contents of the section at `index`, e.g. a string table.
+  fn section(&self, sections: &[Section], index: u32) -> Result<&'a [u8]> {
+    let section = sections
+      .get(index as usize)
+      .context("invalid section index")?;
+    self.bytes(section.offset, section.size)
+  }
+
+  /// The NUL-terminated string at `offset` in the string table `strings`.
+  fn string(&self, strings: &[u8], offset: u64) -> Result<String> {
+    let bytes = strings
+      .get(offset as usize..)
+      .context("invalid string offset")?;
+    let end = bytes
+      .iter()
+      .position(|byte| *byte == 0)
+      .context("unterminated string")?;
+    Ok(String::from_utf8_lossy(&bytes[..end]).into_owned())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// manylinux2014 with other symbol versions.
+  fn policy(versions: &'static [(&'static str, &'static str)]) -> Policy {
+    Policy {
+      versions,
+      ..MANYLINUX2014
+    }
+  }
+
+  #[test]
+  fn allows_versions_up_to_the_policy() {
+    assert!(MANYLINUX2014.allows("GLIBC_2.2.5"));
+    assert!(MANYLINUX2014.allows("GLIBC_2.17"));
+    assert!(!MANYLINUX2014.allows("GLIBC_2.18"));
+    assert!(!MANYLINUX2014.allows("GLIBC_2.34"));
+    assert!(!MANYLINUX2014.allows("GLIBC_PRIVATE"));
+    assert!(MANYLINUX2014.allows("GLIBCXX_3.4.19"));
+    assert!(!MANYLINUX2014.allows("GLIBCXX_3.4.20"));
+    assert!(MANYLINUX2014.allows("CXXABI_TM_1"));
+    assert!(MANYLINUX2014.allows("OPENSSL_3.0.0"));
+  }
+
+  #[test]
+  fn platform_tags() {
+    assert_eq!(
+      MANYLINUX2014.platform_tag("linux_x86_64").unwrap(),
+      "manylinux_2_17_x86_64.manylinux2014_x86_64"
+    );
+    assert!(MANYLINUX2014.platform_tag("win_amd64").is_err());
+  }
+
+  #[test]
+  fn rejects_other_files() {
+    assert!(Dependencies::read(b"#!/bin/sh\n").is_err());
+    assert!(Dependencies::read(b"\x7fELF\x02\x01\x01").is_err());
+  }
+
+  // The test binary itself is a dynamically linked glibc executable.
+  #[cfg(all(target_os = "linux", target_env = "gnu"))]
+  #[test]
+  fn reads_the_test_binary() {
+    let exe = std::env::current_exe().unwrap();
+    let deps = Dependencies::read(&fs::read(&exe).unwrap()).unwrap();
+    assert!(deps.needed.iter().any(|library| library == "libc.so.6"));
+    assert!(deps.versions.iter().any(|((library, version), symbols)| {
+      library == "libc.so.6" && version.starts_with("GLIBC_2.") && !symbols.is_empty()
+    }));
+
+    assert!(policy(&[("GLIBC", "99")]).audit(&exe).is_ok());
+    let err = policy(&[("GLIBC", "2.0")]).audit(&exe).unwrap_err();
+    assert!(format!("{}", err).contains("requires GLIBC_2."));
+  }
+}
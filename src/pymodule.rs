@@ -0,0 +1,341 @@
+use anyhow::{bail, Result};
+use proc_macro2::{Span, TokenTree};
+use std::fs;
+use std::path::{Path, PathBuf};
+use syn::meta::ParseNestedMeta;
+use syn::{Attribute, Item, LitStr, Meta};
+
+use crate::sources;
+
+/// A `#[pymodule]` item in the source.
+struct PyModule {
+  /// The name Python imports it by.
+  name: String,
+  span: Span,
+}
+
+/// Checks that `src` or one of the modules it declares out of line defines a
+/// `#[pymodule]` whose Python name is `module_name`, since Python can only
+/// import the built library through an init function with that name.
+///
+/// Sources that do not parse are let through so that rustc reports the error,
+/// and finding no `#[pymodule]` at all is only a warning, since one may be
+/// generated by a macro.
+pub fn check(input: &Path, src: &str, module_name: &str) -> Result<()> {
+  let Ok(file) = syn::parse_file(src) else {
+    return Ok(());
+  };
+
+  // Each module with the file it is defined in.
+  let mut modules = Vec::new();
+  let mut found = Vec::new();
+  find_pymodules(&file.items, &mut found);
+  modules.extend(found.drain(..).map(|module| (input.to_owned(), module)));
+  let root = input.parent().unwrap_or_else(|| Path::new(""));
+  for path in sources::local_files(input) {
+    let path = root.join(path);
+    let Some(file) = fs::read_to_string(&path)
+      .ok()
+      .and_then(|src| syn::parse_file(&src).ok())
+    else {
+      continue;
+    };
+    find_pymodules(&file.items, &mut found);
+    modules.extend(found.drain(..).map(|module| (path.clone(), module)));
+  }
+  if modules.iter().any(|(_, module)| module.name == module_name) {
+    return Ok(());
+  }
+
+  let location = |(path, module): &(PathBuf, PyModule)| {
+    format!("{}:{}", path.display(), module.span.start().line)
+  };
+  match modules.as_slice() {
+    [] => {
+      eprintln!(
+        "warning: {}: no #[pymodule] found. Python imports the module as `{}`, so unless a macro \
+         generates it, add\n\n#[pymodule]\nfn {}(m: &Bound<'_, PyModule>) -> PyResult<()>\n",
+        input.display(),
+        module_name,
+        module_name
+      );
+      Ok(())
+    }
+    [module] => bail!(
+      "{}: #[pymodule] `{}` does not match the file name. Python imports the module as `{}`, \
+       so rename the function to `{}`, add #[pyo3(name = \"{}\")] to it, or rename the file to `{}.rs`",
+      location(module),
+      module.1.name,
+      module_name,
+      module_name,
+      module_name,
+      module.1.name
+    ),
+    _ => bail!(
+      "{}: none of the #[pymodule]s ({}) match the file name. Python imports the module as `{}`, \
+       so one of them must be named `{}` or have #[pyo3(name = \"{}\")]",
+      input.display(),
+      modules
+        .iter()
+        .map(|module| format!("`{}` at {}", module.1.name, location(module)))
+        .collect::<Vec<_>>()
+        .join(", "),
+      module_name,
+      module_name,
+      module_name
+    ),
+  }
+}
+
+/// Module init code to append to `src` if it defines no `#[pymodule]`,
+/// registering every top-level `#[pyfunction]` and `#[pyclass]` under
+/// `module_name`.
+///
+/// Returns `None` if `src` already has a `#[pymodule]` or does not parse.
+pub fn generate(src: &str, module_name: &str) -> Option<String> {
+  let file = syn::parse_file(src).ok()?;
+  let mut modules = Vec::new();
+  find_pymodules(&file.items, &mut modules);
+  if !modules.is_empty() {
+    return None;
+  }
+
+  let mut body = String::new();
+  for item in &file.items {
+    match item {
+      Item::Fn(item) if has_attr(&item.attrs, "pyfunction") => body.push_str(&format!(
+        "  m.add_function(::pyo3::wrap_pyfunction!({}, m)?)?;\n",
+        item.sig.ident
+      )),
+      Item::Struct(syn::ItemStruct { attrs, ident, .. })
+      | Item::Enum(syn::ItemEnum { attrs, ident, .. })
+        if has_attr(attrs, "pyclass") =>
+      {
+        body.push_str(&format!("  m.add_class::<{}>()?;\n", ident))
+      }
+      _ => {}
+    }
+  }
+
+  Some(format!(
+    r#"
+#[::pyo3::pymodule]
+#[pyo3(name = "{}")]
+fn __single_pyo3_module(
+  m: &::pyo3::Bound<'_, ::pyo3::types::PyModule>,
+) -> ::pyo3::PyResult<()> {{
+  use ::pyo3::types::PyModuleMethods as _;
+{}  Ok(())
+}}
+"#,
+    module_name, body
+  ))
+}
+
+/// The generation of pyo3's API that a source is written against, judged by
+/// how its `#[pymodule]` function takes the module.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ApiEra {
+  /// `m: &PyModule`, as in pyo3 0.20 and earlier.
+  GilRefs,
+  /// `m: &Bound<'_, PyModule>`, as in pyo3 0.21 and later.
+  Bound,
+}
+
+impl ApiEra {
+  /// The era that pyo3 `version` requires, or `None` if it accepts both or
+  /// the version cannot be told from the requirement.
+  ///
+  /// 0.21 and 0.22 still accept GIL refs behind a deprecation, and 0.23
+  /// removed them.
+  fn of_version(version: &str) -> Option<ApiEra> {
+    let version = version.trim_start_matches(['=', '^', '~', ' ']);
+    let mut parts = version.split('.');
+    let (Some("0"), Some(minor)) = (parts.next(), parts.next()) else {
+      return None;
+    };
+    match minor.parse::<u32>().ok()? {
+      0..=20 => Some(ApiEra::GilRefs),
+      21 | 22 => None,
+      _ => Some(ApiEra::Bound),
+    }
+  }
+}
+
+/// The API era of the first `#[pymodule]` function in `src` that takes the
+/// module as an argument, with the line it is declared on.
+pub fn api_era(src: &str) -> Option<(ApiEra, usize)> {
+  let file = syn::parse_file(src).ok()?;
+  file.items.iter().find_map(|item| {
+    let Item::Fn(item) = item else {
+      return None;
+    };
+    if !has_attr(&item.attrs, "pymodule") {
+      return None;
+    }
+    item.sig.inputs.iter().find_map(|arg| {
+      let syn::FnArg::Typed(arg) = arg else {
+        return None;
+      };
+      let syn::Type::Reference(ty) = &*arg.ty else {
+        return None;
+      };
+      let syn::Type::Path(path) = &*ty.elem else {
+        return None;
+      };
+      let era = match path.path.segments.last()?.ident.to_string().as_str() {
+        "PyModule" => ApiEra::GilRefs,
+        "Bound" => ApiEra::Bound,
+        _ => return None,
+      };
+      Some((era, item.sig.ident.span().start().line))
+    })
+  })
+}
+
+/// A warning if `src` is written against a different API era than pyo3
+/// `version` provides, since the resulting compile errors rarely say so.
+pub fn api_mismatch(input: &Path, src: &str, version: &str) -> Option<String> {
+  let (era, line) = api_era(src)?;
+  let required = ApiEra::of_version(version)?;
+  if era == required {
+    return None;
+  }
+
+  Some(match era {
+    ApiEra::GilRefs => format!(
+      "{}:{}: the #[pymodule] takes `&PyModule`, which pyo3 {} no longer supports. \
+       Build with --pyo3 0.20, or take `m: &Bound<'_, PyModule>` instead",
+      input.display(),
+      line,
+      version
+    ),
+    ApiEra::Bound => format!(
+      "{}:{}: the #[pymodule] takes `&Bound<'_, PyModule>`, which needs pyo3 0.21 or later \
+       but {} was selected. Build with --pyo3 latest",
+      input.display(),
+      line,
+      version
+    ),
+  })
+}
+
+fn find_pymodules(items: &[Item], modules: &mut Vec<PyModule>) {
+  for item in items {
+    let (attrs, ident) = match item {
+      Item::Fn(item) => (&item.attrs, &item.sig.ident),
+      Item::Mod(item) => {
+        if !has_attr(&item.attrs, "pymodule") {
+          if let Some((_, items)) = &item.content {
+            find_pymodules(items, modules);
+          }
+        }
+        (&item.attrs, &item.ident)
+      }
+      _ => continue,
+    };
+
+    if has_attr(attrs, "pymodule") {
+      modules.push(PyModule {
+        name: name_override(attrs).unwrap_or_else(|| ident.to_string()),
+        span: ident.span(),
+      });
+    }
+  }
+}
+
+/// Whether `attrs` contains `#[name]`, possibly with a path such as
+/// `#[pyo3::name]`, and possibly with arguments.
+pub fn has_attr(attrs: &[Attribute], name: &str) -> bool {
+  attrs.iter().any(|attr| {
+    attr
+      .path()
+      .segments
+      .last()
+      .is_some_and(|segment| segment.ident == name)
+  })
+}
+
+/// The Python name given by `#[pyo3(name = "...")]`, or by the same argument
+/// to another pyo3 attribute such as `#[pymodule(name = "...")]`.
+pub fn name_override(attrs: &[Attribute]) -> Option<String> {
+  let mut name = None;
+  for_each_attr_arg(attrs, |meta| {
+    if !meta.path.is_ident("name") {
+      return Ok(false);
+    }
+    name = Some(meta.value()?.parse::<LitStr>()?.value());
+    Ok(true)
+  });
+  name
+}
+
+/// Calls `visit` for each argument of the attributes in `attrs` that take a
+/// list, as in `#[pyo3(name = "x", get)]`. `visit` returns whether it parsed
+/// the argument; those it leaves are skipped, and any that cannot be parsed
+/// just end the walk of that attribute.
+pub fn for_each_attr_arg(
+  attrs: &[Attribute],
+  mut visit: impl FnMut(&ParseNestedMeta) -> syn::Result<bool>,
+) {
+  for attr in attrs {
+    if !matches!(attr.meta, Meta::List(_)) {
+      continue;
+    }
+    let _ = attr.parse_nested_meta(|meta| {
+      if visit(&meta)? {
+        return Ok(());
+      }
+      if meta.input.peek(syn::Token![=]) {
+        skip_value(&meta)?;
+      } else if meta.input.peek(syn::token::Paren) {
+        meta.parse_nested_meta(|_| Ok(()))?;
+      }
+      Ok(())
+    });
+  }
+}
+
+/// Skips the value of a `key = value` attribute argument, which need not be
+/// an expression, as in `signature = (a, /)`.
+fn skip_value(meta: &ParseNestedMeta) -> syn::Result<()> {
+  let value = meta.value()?;
+  while !value.is_empty() && !value.peek(syn::Token![,]) {
+    value.parse::<TokenTree>()?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Writes `files` to a fresh directory and checks the first as `foo.rs`.
+  fn check_files(name: &str, files: &[(&str, &str)]) -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("pymodule-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for (file, src) in files {
+      fs::write(dir.join(file), src).unwrap();
+    }
+    let result = check(&dir.join(files[0].0), files[0].1, "foo");
+    fs::remove_dir_all(&dir).unwrap();
+    result
+  }
+
+  #[test]
+  fn finds_out_of_line_modules() {
+    let init = "#[pymodule]\nfn foo(m: &Bound<'_, PyModule>) -> PyResult<()> { Ok(()) }\n";
+    assert!(check_files("init", &[("foo.rs", "mod init;\n"), ("init.rs", init)]).is_ok());
+
+    let init = "#[pymodule]\nfn bar(m: &Bound<'_, PyModule>) -> PyResult<()> { Ok(()) }\n";
+    let err = check_files("mismatch", &[("foo.rs", "mod init;\n"), ("init.rs", init)]).unwrap_err();
+    assert!(format!("{}", err).contains("init.rs:2: #[pymodule] `bar`"));
+  }
+
+  #[test]
+  fn allows_generated_modules() {
+    let src =
+      "macro_rules! module { ($name:ident) => { #[pymodule] fn $name() {} } }\nmodule!(foo);\n";
+    assert!(check_files("macro", &[("foo.rs", src)]).is_ok());
+  }
+}
@@ -0,0 +1,212 @@
+use anyhow::{bail, Context, Result};
+use std::env;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The interpreter used when none is selected explicitly.
+fn default_interpreter() -> &'static str {
+  if cfg!(windows) {
+    "python"
+  } else {
+    "python3"
+  }
+}
+
+/// Resolves a relative path such as `./py` against the current directory,
+/// since pyo3's build script runs elsewhere. Bare names such as `python3` are
+/// left to be looked up on the `PATH`.
+fn absolute(python: PathBuf) -> PathBuf {
+  if python.is_relative() && python.components().count() > 1 {
+    if let Ok(dir) = env::current_dir() {
+      return dir.join(python);
+    }
+  }
+  python
+}
+
+/// Picks the interpreter to build against: `explicit` if given, then
+/// `PYO3_PYTHON`, then the active virtualenv or conda environment, and
+/// finally whichever Python is on the `PATH`.
+pub fn find_interpreter(explicit: Option<&str>) -> PathBuf {
+  if let Some(python) = explicit {
+    return absolute(PathBuf::from(python));
+  }
+
+  if let Some(python) = env::var_os("PYO3_PYTHON").filter(|python| !python.is_empty()) {
+    return absolute(PathBuf::from(python));
+  }
+
+  let bin = |prefix: PathBuf, dir: &str| {
+    if cfg!(windows) {
+      prefix.join(dir).join("python.exe")
+    } else {
+      prefix.join("bin").join("python")
+    }
+  };
+  if let Some(venv) = env::var_os("VIRTUAL_ENV") {
+    return bin(PathBuf::from(venv), "Scripts");
+  }
+  if let Some(conda) = env::var_os("CONDA_PREFIX") {
+    return bin(PathBuf::from(conda), "");
+  }
+
+  PathBuf::from(default_interpreter())
+}
+
+/// Runs `code` with `python` and returns its trimmed standard output.
+pub fn query(python: &Path, code: &str) -> Result<String> {
+  let output = Command::new(python)
+    .args(["-c", code])
+    .output()
+    .with_context(|| format!("Could not run {}", python.display()))?;
+
+  if !output.status.success() {
+    bail!(
+      "{} failed: {}",
+      python.display(),
+      String::from_utf8_lossy(&output.stderr).trim()
+    );
+  }
+
+  Ok(String::from_utf8(output.stdout)?.trim().to_owned())
+}
+
+/// The major and minor version of `python`.
+pub fn version(python: &Path) -> Result<(u32, u32)> {
+  parse_version(&query(
+    python,
+    "import sys; print('%d.%d' % sys.version_info[:2])",
+  )?)
+}
+
+/// The filename suffix `python` expects for extension modules, including its
+/// ABI tag, e.g. `.cpython-311-x86_64-linux-gnu.so`.
+pub fn ext_suffix(python: &Path) -> Result<String> {
+  let suffix = query(
+    python,
+    "import sysconfig; print(sysconfig.get_config_var('EXT_SUFFIX') or '')",
+  )?;
+  if suffix.is_empty() {
+    bail!("{} does not report an EXT_SUFFIX", python.display());
+  }
+  Ok(suffix)
+}
+
+/// The untagged extension module suffix for the host platform.
+pub fn plain_suffix() -> &'static str {
+  if cfg!(windows) {
+    ".pyd"
+  } else {
+    ".so"
+  }
+}
+
+/// A PEP 425 compatibility tag, e.g. `cp311-cp311-linux_x86_64`.
+pub struct WheelTag {
+  pub interpreter: String,
+  pub abi: String,
+  pub platform: String,
+}
+
+impl fmt::Display for WheelTag {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}-{}-{}", self.interpreter, self.abi, self.platform)
+  }
+}
+
+/// The compatibility tag for extension modules built against `python`, or
+/// against the stable ABI from version `abi3` onwards.
+pub fn wheel_tag(python: &Path, abi3: Option<(u32, u32)>) -> Result<WheelTag> {
+  let tag = query(
+    python,
+    r#"
+import sys, sysconfig
+impl = {"cpython": "cp", "pypy": "pp"}.get(sys.implementation.name, sys.implementation.name)
+interpreter = "%s%d%d" % (impl, sys.version_info[0], sys.version_info[1])
+if impl == "cp":
+    abi = interpreter + getattr(sys, "abiflags", "")
+else:
+    abi = "_".join((sysconfig.get_config_var("SOABI") or "none").split("-")[:2])
+platform = sysconfig.get_platform().replace("-", "_").replace(".", "_")
+print(interpreter, abi.replace(".", "_"), platform)
+"#,
+  )?;
+
+  let mut parts = tag.split_whitespace().map(str::to_owned);
+  let (Some(interpreter), Some(abi), Some(platform)) = (parts.next(), parts.next(), parts.next())
+  else {
+    bail!("Unexpected wheel tag from {}: {}", python.display(), tag);
+  };
+
+  Ok(match abi3 {
+    Some((major, minor)) => WheelTag {
+      interpreter: format!("cp{}{}", major, minor),
+      abi: "abi3".into(),
+      platform,
+    },
+    None => WheelTag {
+      interpreter,
+      abi,
+      platform,
+    },
+  })
+}
+
+/// Parses a Python version such as `3.8`.
+pub fn parse_version(version: &str) -> Result<(u32, u32)> {
+  let parsed = version
+    .split_once('.')
+    .and_then(|(major, minor)| Some((major.parse().ok()?, minor.parse().ok()?)));
+  match parsed {
+    Some((3, minor)) => Ok((3, minor)),
+    _ => bail!("Invalid Python version `{}`, expected e.g. 3.8", version),
+  }
+}
+
+/// The suffix for stable ABI extension modules on the host platform.
+pub fn abi3_suffix() -> &'static str {
+  if cfg!(windows) {
+    ".pyd"
+  } else {
+    ".abi3.so"
+  }
+}
+
+/// The site-packages directory extension modules are installed into for
+/// `python`, which must belong to a virtualenv or conda environment.
+pub fn site_packages(python: &Path) -> Result<PathBuf> {
+  let dir = query(
+    python,
+    r#"
+import os, sys, sysconfig
+if sys.prefix != sys.base_prefix or os.path.exists(os.path.join(sys.prefix, "conda-meta")):
+    print(sysconfig.get_paths()["platlib"])
+"#,
+  )?;
+  if dir.is_empty() {
+    bail!(
+      "{} is not in a virtualenv or conda environment. Activate one, or select one with --python.",
+      python.display()
+    );
+  }
+  Ok(PathBuf::from(dir))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn relative_interpreters_are_absolute() {
+    assert_eq!(absolute("python3".into()), Path::new("python3"));
+    assert_eq!(
+      absolute("./py".into()),
+      env::current_dir().unwrap().join("./py")
+    );
+    assert_eq!(
+      absolute("/usr/bin/python3".into()),
+      Path::new("/usr/bin/python3")
+    );
+  }
+}
@@ -0,0 +1,119 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Files besides `input` that its crate reads from disk: out-of-line `mod`
+/// declarations and `include!`, `include_str!` and `include_bytes!` paths,
+/// found by scanning the source text rather than parsing it.
+///
+/// Paths are relative to the directory containing `input`, and only files that
+/// exist and stay inside that directory are returned, since those are the
+/// ones that can be copied next to the generated `lib.rs`.
+pub fn local_files(input: &Path) -> Vec<PathBuf> {
+  let root = input.parent().unwrap_or_else(|| Path::new(""));
+  let mut files = Vec::new();
+  // Each entry is a file to scan, relative to `root`, and whether it is
+  // `input` itself.
+  let mut queue = vec![(PathBuf::new(), true)];
+  while let Some((file, is_root)) = queue.pop() {
+    let path = if is_root {
+      input.to_owned()
+    } else {
+      root.join(&file)
+    };
+    let Ok(src) = fs::read_to_string(&path) else {
+      continue;
+    };
+
+    let dir = file.parent().map(Path::to_owned).unwrap_or_default();
+    let mod_dir = if is_root || file.file_name().is_some_and(|name| name == "mod.rs") {
+      dir.clone()
+    } else {
+      dir.join(file.file_stem().unwrap_or_default())
+    };
+
+    let mut found = Vec::new();
+    for line in src.lines() {
+      if let Some(name) = mod_decl(line) {
+        let candidates = [
+          mod_dir.join(format!("{}.rs", name)),
+          mod_dir.join(name).join("mod.rs"),
+        ];
+        if let Some(child) = candidates.into_iter().find(|c| root.join(c).is_file()) {
+          found.push((child, true));
+        }
+      }
+    }
+    for include in includes(&src) {
+      found.push((dir.join(include), false));
+    }
+
+    for (child, is_module) in found {
+      let is_local = child
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+      if is_local && root.join(&child).is_file() && !files.contains(&child) {
+        files.push(child.clone());
+        if is_module {
+          queue.push((child, false));
+        }
+      }
+    }
+  }
+
+  files
+}
+
+/// The module name declared by an out-of-line `mod name;` item on `line`.
+fn mod_decl(line: &str) -> Option<&str> {
+  let mut line = line.trim();
+  if let Some(rest) = line.strip_prefix("pub") {
+    line = rest.trim_start();
+    if line.starts_with('(') {
+      line = line[line.find(')')? + 1..].trim_start();
+    }
+  }
+
+  let name = line.strip_prefix("mod ")?.strip_suffix(';')?.trim();
+  let is_ident = !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+  is_ident.then_some(name)
+}
+
+/// String literal arguments of the `include!` family of macros in `src`.
+fn includes(src: &str) -> Vec<&str> {
+  let mut paths = Vec::new();
+  for mac in ["include!(", "include_str!(", "include_bytes!("] {
+    let mut rest = src;
+    while let Some(i) = rest.find(mac) {
+      rest = &rest[i + mac.len()..];
+      if let Some(lit) = rest.trim_start().strip_prefix('"') {
+        if let Some(end) = lit.find('"') {
+          paths.push(&lit[..end]);
+        }
+      }
+    }
+  }
+  paths
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn mod_decls() {
+    assert_eq!(mod_decl("mod util;"), Some("util"));
+    assert_eq!(mod_decl("  pub(crate) mod util ;"), Some("util"));
+    assert_eq!(mod_decl("mod util {"), None);
+    assert_eq!(mod_decl("// mod util;"), None);
+  }
+
+  #[test]
+  fn include_paths() {
+    let src = r#"
+      const DATA: &[u8] = include_bytes!("data.bin");
+      include!( "gen.rs");
+      static HELP: &str = include_str!(concat!(env!("OUT_DIR"), "/help.txt"));
+    "#;
+    assert_eq!(includes(src), ["gen.rs", "data.bin"]);
+  }
+}
@@ -0,0 +1,478 @@
+use proc_macro2::{TokenStream, TokenTree};
+use syn::{
+  Attribute, Fields, FnArg, GenericArgument, ImplItem, Item, Pat, PathArguments, ReturnType, Type,
+};
+
+use crate::pymodule::{for_each_attr_arg, has_attr, name_override};
+
+/// Generates a `.pyi` stub for the `#[pyfunction]`s, `#[pyclass]`es and
+/// `#[pymethods]` at the top level of `src`, or `None` if it does not parse.
+///
+/// Rust types are mapped to their Python equivalents where pyo3 converts
+/// them, and to `Any` otherwise.
+pub fn generate(src: &str) -> Option<String> {
+  let file = syn::parse_file(src).ok()?;
+
+  let classes = file
+    .items
+    .iter()
+    .filter_map(|item| match item {
+      Item::Struct(item) if has_attr(&item.attrs, "pyclass") => Some((
+        item.ident.to_string(),
+        python_name(&item.attrs, &item.ident),
+      )),
+      Item::Enum(item) if has_attr(&item.attrs, "pyclass") => Some((
+        item.ident.to_string(),
+        python_name(&item.attrs, &item.ident),
+      )),
+      _ => None,
+    })
+    .collect::<Vec<_>>();
+  let stubs = Stubs { classes: &classes };
+
+  let mut out = String::from("from typing import Any\n");
+  for item in &file.items {
+    match item {
+      Item::Fn(item) if has_attr(&item.attrs, "pyfunction") => {
+        out.push('\n');
+        out.push_str(&stubs.function(&item.attrs, &item.sig, None, ""));
+      }
+      Item::Struct(item) if has_attr(&item.attrs, "pyclass") => {
+        let mut body = String::new();
+        let get_all = has_attr_arg(&item.attrs, "get_all");
+        if let Fields::Named(fields) = &item.fields {
+          for field in &fields.named {
+            if get_all || has_attr_arg(&field.attrs, "get") {
+              body.push_str(&format!(
+                "    {}: {}\n",
+                name_override(&field.attrs)
+                  .or_else(|| field.ident.as_ref().map(ToString::to_string))
+                  .unwrap_or_default(),
+                stubs.python_type(&field.ty)
+              ));
+            }
+          }
+        }
+        body.push_str(&stubs.methods(&file.items, &item.ident));
+        out.push_str(&class(&item.attrs, &item.ident, &body));
+      }
+      Item::Enum(item) if has_attr(&item.attrs, "pyclass") => {
+        let mut body = String::new();
+        let class_name = python_name(&item.attrs, &item.ident);
+        for variant in &item.variants {
+          if matches!(variant.fields, Fields::Unit) {
+            body.push_str(&format!(
+              "    {}: {}\n",
+              python_name(&variant.attrs, &variant.ident),
+              class_name
+            ));
+          }
+        }
+        body.push_str(&stubs.methods(&file.items, &item.ident));
+        out.push_str(&class(&item.attrs, &item.ident, &body));
+      }
+      _ => {}
+    }
+  }
+
+  Some(out)
+}
+
+struct Stubs<'a> {
+  /// Rust and Python names of the `#[pyclass]`es in the file.
+  classes: &'a [(String, String)],
+}
+
+impl Stubs<'_> {
+  /// The body of the class for `ident`, taken from every `#[pymethods]` block
+  /// implemented for it.
+  fn methods(&self, items: &[Item], ident: &syn::Ident) -> String {
+    let mut out = String::new();
+    for item in items {
+      let Item::Impl(block) = item else {
+        continue;
+      };
+      let is_target = matches!(&*block.self_ty, Type::Path(ty) if ty.path.is_ident(ident));
+      if !is_target || !has_attr(&block.attrs, "pymethods") {
+        continue;
+      }
+
+      for item in &block.items {
+        match item {
+          ImplItem::Fn(method) => {
+            out.push_str(&self.function(&method.attrs, &method.sig, Some(ident), "    "))
+          }
+          ImplItem::Const(constant) if has_attr(&constant.attrs, "classattr") => {
+            out.push_str(&format!(
+              "    {}: {}\n",
+              python_name(&constant.attrs, &constant.ident),
+              self.python_type(&constant.ty)
+            ))
+          }
+          _ => {}
+        }
+      }
+    }
+
+    out
+  }
+
+  /// A `def` for a function or method. `class` is the type a method belongs
+  /// to, and `None` for module-level functions.
+  fn function(
+    &self,
+    attrs: &[Attribute],
+    sig: &syn::Signature,
+    class: Option<&syn::Ident>,
+    indent: &str,
+  ) -> String {
+    let mut out = String::new();
+    let mut name = python_name(attrs, &sig.ident);
+    let mut params = Vec::new();
+    let mut ret = match &sig.output {
+      ReturnType::Default => "None".to_owned(),
+      ReturnType::Type(_, ty) => self.python_type_in(ty, class),
+    };
+
+    let mut inputs = sig.inputs.iter().peekable();
+    if class.is_some() {
+      if has_attr(attrs, "new") {
+        name = "__init__".into();
+        ret = "None".into();
+        params.push("self".to_owned());
+      } else if has_attr(attrs, "staticmethod") {
+        out.push_str(&format!("{}@staticmethod\n", indent));
+      } else if has_attr(attrs, "classmethod") {
+        out.push_str(&format!("{}@classmethod\n", indent));
+        params.push("cls".to_owned());
+        inputs.next();
+      } else if has_attr(attrs, "getter") {
+        name = name.strip_prefix("get_").unwrap_or(&name).to_owned();
+        out.push_str(&format!("{}@property\n", indent));
+        params.push("self".to_owned());
+      } else if has_attr(attrs, "setter") {
+        name = name.strip_prefix("set_").unwrap_or(&name).to_owned();
+        out.push_str(&format!("{}@{}.setter\n", indent, name));
+        ret = "None".into();
+        params.push("self".to_owned());
+      } else {
+        params.push("self".to_owned());
+      }
+
+      // The receiver is `self` above, whether it is written as `&self` or
+      // as an explicit `slf: PyRef<'_, Self>`.
+      if let Some(arg) = inputs.peek() {
+        let is_receiver = match arg {
+          FnArg::Receiver(_) => true,
+          FnArg::Typed(arg) => matches!(&*arg.pat, Pat::Ident(pat) if pat.ident == "slf"),
+        };
+        if is_receiver {
+          inputs.next();
+        }
+      }
+    }
+
+    let mut args = Vec::new();
+    for arg in inputs {
+      let FnArg::Typed(arg) = arg else {
+        continue;
+      };
+      if is_python_token(&arg.ty) {
+        continue;
+      }
+      let name = match &*arg.pat {
+        Pat::Ident(pat) => unraw(&pat.ident),
+        _ => format!("arg{}", params.len() + args.len()),
+      };
+      args.push((name, self.python_type_in(&arg.ty, class)));
+    }
+
+    match signature(attrs) {
+      Some(signature) => {
+        let type_of = |name: &str| {
+          args
+            .iter()
+            .find(|(arg, _)| arg == name)
+            .map_or("Any", |(_, ty)| ty.as_str())
+        };
+        for param in signature {
+          params.push(match param {
+            Param::Named(name, false) => format!("{}: {}", name, type_of(&name)),
+            Param::Named(name, true) => format!("{}: {} = ...", name, type_of(&name)),
+            Param::Args(name) => format!("*{}: Any", name),
+            Param::Kwargs(name) => format!("**{}: Any", name),
+            Param::Marker(marker) => marker,
+          });
+        }
+      }
+      None => params.extend(args.iter().map(|(name, ty)| format!("{}: {}", name, ty))),
+    }
+
+    out.push_str(&format!(
+      "{}def {}({}) -> {}: ...\n",
+      indent,
+      name,
+      params.join(", "),
+      ret
+    ));
+    out
+  }
+
+  /// The Python name of the `#[pyclass]` named `name` in Rust, or `Any` if
+  /// there is no such class.
+  fn class_name(&self, name: &str) -> String {
+    self
+      .classes
+      .iter()
+      .find(|(rust, _)| rust == name)
+      .map_or_else(|| "Any".to_owned(), |(_, python)| python.clone())
+  }
+
+  fn python_type(&self, ty: &Type) -> String {
+    self.python_type_in(ty, None)
+  }
+
+  /// The Python annotation for `ty`, where `Self` refers to `class`.
+  fn python_type_in(&self, ty: &Type, class: Option<&syn::Ident>) -> String {
+    let path = match ty {
+      Type::Reference(ty) => {
+        if let Type::Slice(slice) = &*ty.elem {
+          if is_named(&slice.elem, "u8") {
+            return "bytes".into();
+          }
+        }
+        return self.python_type_in(&ty.elem, class);
+      }
+      Type::Paren(ty) => return self.python_type_in(&ty.elem, class),
+      Type::Group(ty) => return self.python_type_in(&ty.elem, class),
+      Type::Slice(ty) => return format!("list[{}]", self.python_type_in(&ty.elem, class)),
+      Type::Array(ty) => return format!("list[{}]", self.python_type_in(&ty.elem, class)),
+      Type::Tuple(ty) if ty.elems.is_empty() => return "None".into(),
+      Type::Tuple(ty) => {
+        let elems = ty
+          .elems
+          .iter()
+          .map(|elem| self.python_type_in(elem, class))
+          .collect::<Vec<_>>();
+        return format!("tuple[{}]", elems.join(", "));
+      }
+      Type::Path(ty) => &ty.path,
+      _ => return "Any".into(),
+    };
+
+    let Some(segment) = path.segments.last() else {
+      return "Any".into();
+    };
+    let args = match &segment.arguments {
+      PathArguments::AngleBracketed(args) => args
+        .args
+        .iter()
+        .filter_map(|arg| match arg {
+          GenericArgument::Type(ty) => Some(ty),
+          _ => None,
+        })
+        .collect::<Vec<_>>(),
+      _ => Vec::new(),
+    };
+    let arg = |i: usize| {
+      args
+        .get(i)
+        .map_or_else(|| "Any".to_owned(), |ty| self.python_type_in(ty, class))
+    };
+
+    let name = segment.ident.to_string();
+    match name.as_str() {
+      "i8" | "i16" | "i32" | "i64" | "i128" | "isize" | "u8" | "u16" | "u32" | "u64" | "u128"
+      | "usize" | "BigInt" | "BigUint" | "PyInt" | "PyLong" => "int".into(),
+      "f32" | "f64" | "PyFloat" => "float".into(),
+      "bool" | "PyBool" => "bool".into(),
+      "String" | "str" | "char" | "Cow" | "PyString" | "OsString" | "PathBuf" => "str".into(),
+      "PyBytes" => "bytes".into(),
+      "Vec" | "VecDeque" => format!("list[{}]", arg(0)),
+      "HashSet" | "BTreeSet" => format!("set[{}]", arg(0)),
+      "HashMap" | "BTreeMap" => format!("dict[{}, {}]", arg(0), arg(1)),
+      "Option" => format!("{} | None", arg(0)),
+      "PyResult" | "Result" | "Box" | "Arc" | "Rc" | "Py" | "Bound" | "Borrowed" | "PyRef"
+      | "PyRefMut" => arg(0),
+      "PyList" => "list[Any]".into(),
+      "PyDict" => "dict[Any, Any]".into(),
+      "PyTuple" => "tuple[Any, ...]".into(),
+      "PySet" => "set[Any]".into(),
+      "Self" => class.map_or_else(
+        || "Any".to_owned(),
+        |class| self.class_name(&class.to_string()),
+      ),
+      _ => self.class_name(&name),
+    }
+  }
+}
+
+/// A parameter in a `#[pyo3(signature = (...))]`.
+enum Param {
+  /// A parameter, and whether it has a default value.
+  Named(String, bool),
+  /// `*args`.
+  Args(String),
+  /// `**kwargs`.
+  Kwargs(String),
+  /// The `/` ending the positional-only parameters, or the `*` starting the
+  /// keyword-only ones.
+  Marker(String),
+}
+
+/// The parameters given by `#[pyo3(signature = (...))]`, if there is one.
+fn signature(attrs: &[Attribute]) -> Option<Vec<Param>> {
+  let mut params = None;
+  for_each_attr_arg(attrs, |meta| {
+    if !meta.path.is_ident("signature") {
+      return Ok(false);
+    }
+    let value = meta.value()?;
+    let content;
+    syn::parenthesized!(content in value);
+    params = Some(signature_params(content.parse()?));
+    Ok(true)
+  });
+  params
+}
+
+fn signature_params(tokens: TokenStream) -> Vec<Param> {
+  let is_punct =
+    |token: &TokenTree, ch: char| matches!(token, TokenTree::Punct(punct) if punct.as_char() == ch);
+
+  let mut params = Vec::new();
+  let mut tokens = tokens.into_iter().peekable();
+  while tokens.peek().is_some() {
+    let param = tokens
+      .by_ref()
+      .take_while(|token| !is_punct(token, ','))
+      .collect::<Vec<_>>();
+    let stars = param
+      .iter()
+      .take_while(|token| is_punct(token, '*'))
+      .count();
+    let name = match param.get(stars) {
+      Some(TokenTree::Ident(ident)) => Some(unraw(ident)),
+      _ => None,
+    };
+    params.push(match (stars, name) {
+      (0, Some(name)) => Param::Named(name, param.len() > 1),
+      (1, Some(name)) => Param::Args(name),
+      (2, Some(name)) => Param::Kwargs(name),
+      _ if param.is_empty() => continue,
+      _ => Param::Marker(param.iter().map(ToString::to_string).collect()),
+    });
+  }
+  params
+}
+
+/// A class definition with the given body, which may be empty.
+fn class(attrs: &[Attribute], ident: &syn::Ident, body: &str) -> String {
+  let body = if body.is_empty() { "    ...\n" } else { body };
+  format!("\nclass {}:\n{}", python_name(attrs, ident), body)
+}
+
+/// The Python name of an item, honouring `#[pyo3(name = "...")]`.
+fn python_name(attrs: &[Attribute], ident: &syn::Ident) -> String {
+  name_override(attrs).unwrap_or_else(|| ident.to_string())
+}
+
+/// Whether any attribute in `attrs` has the bare argument `arg`, as in
+/// `#[pyo3(get, set)]` or `#[pyclass(get_all)]`.
+fn has_attr_arg(attrs: &[Attribute], arg: &str) -> bool {
+  let mut found = false;
+  for_each_attr_arg(attrs, |meta| {
+    found |= meta.path.is_ident(arg);
+    Ok(false)
+  });
+  found
+}
+
+/// The name of `ident` without the `r#` of a raw identifier.
+fn unraw(ident: &syn::Ident) -> String {
+  ident.to_string().trim_start_matches("r#").to_owned()
+}
+
+/// Whether `ty` is `Python<'py>`, the GIL token pyo3 passes implicitly.
+fn is_python_token(ty: &Type) -> bool {
+  is_named(ty, "Python")
+}
+
+fn is_named(ty: &Type, name: &str) -> bool {
+  matches!(ty, Type::Path(ty) if ty.path.segments.last().is_some_and(|s| s.ident == name))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn stub(src: &str) -> String {
+    generate(src).unwrap()
+  }
+
+  #[test]
+  fn signature_defaults() {
+    let src =
+      "#[pyfunction]\n#[pyo3(signature = (a, b=2))]\nfn add(a: i64, b: i64) -> i64 { a + b }";
+    assert!(stub(src).contains("def add(a: int, b: int = ...) -> int: ..."));
+  }
+
+  #[test]
+  fn signature_markers() {
+    let src = r#"
+      #[pyfunction]
+      #[pyo3(signature = (a, /, b = vec![1, 2], *args, c, r#type = None, **kwargs), name = "g")]
+      fn f(a: i64, b: Vec<i64>, args: &Bound<'_, PyTuple>, c: bool, r#type: Option<String>, kwargs: Option<&Bound<'_, PyDict>>) {}
+    "#;
+    assert!(stub(src).contains(
+      "def g(a: int, /, b: list[int] = ..., *args: Any, c: bool, type: str | None = ..., **kwargs: Any) -> None: ..."
+    ));
+
+    let src = "#[pyfunction]\n#[pyo3(signature = (a, *, b))]\nfn f(a: i64, b: i64) {}";
+    assert!(stub(src).contains("def f(a: int, *, b: int) -> None: ..."));
+  }
+
+  #[test]
+  fn signature_of_methods() {
+    let src = r#"
+      #[pyclass]
+      struct Counter {}
+
+      #[pymethods]
+      impl Counter {
+        #[new]
+        #[pyo3(signature = (start=0))]
+        fn new(start: i64) -> Self { Counter {} }
+
+        #[pyo3(signature = (step=1))]
+        fn incr(&mut self, py: Python<'_>, step: i64) {}
+      }
+    "#;
+    let stub = stub(src);
+    assert!(stub.contains("def __init__(self, start: int = ...) -> None: ..."));
+    assert!(stub.contains("def incr(self, step: int = ...) -> None: ..."));
+  }
+
+  #[test]
+  fn python_types() {
+    let classes = [("Point".to_owned(), "Vec2".to_owned())];
+    let stubs = Stubs { classes: &classes };
+    let python_type = |ty: &str| stubs.python_type_in(&syn::parse_str(ty).unwrap(), None);
+    assert_eq!(python_type("PyResult<Vec<i64>>"), "list[int]");
+    assert_eq!(python_type("Option<&str>"), "str | None");
+    assert_eq!(
+      python_type("HashMap<String, (f64, bool)>"),
+      "dict[str, tuple[float, bool]]"
+    );
+    assert_eq!(python_type("&[u8]"), "bytes");
+    assert_eq!(python_type("Bound<'py, PyDict>"), "dict[Any, Any]");
+    assert_eq!(python_type("PyRef<'_, Point>"), "Vec2");
+    assert_eq!(python_type("std::fs::File"), "Any");
+
+    let class = syn::parse_str::<syn::Ident>("Point").unwrap();
+    assert_eq!(
+      stubs.python_type_in(&syn::parse_str("Self").unwrap(), Some(&class)),
+      "Vec2"
+    );
+  }
+}
@@ -0,0 +1,224 @@
+use anyhow::{bail, Context, Result};
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// A target triple to build for, e.g. `aarch64-unknown-linux-gnu`.
+pub struct Target {
+  pub triple: String,
+  /// Whether the target differs from the host, so pyo3 must be told about
+  /// the target's Python instead of asking the host interpreter.
+  pub is_cross: bool,
+}
+
+impl Target {
+  pub fn new(triple: &str) -> Result<Target> {
+    if triple.split('-').count() < 2 {
+      bail!(
+        "Invalid target `{}`, expected e.g. aarch64-unknown-linux-gnu",
+        triple
+      );
+    }
+    Ok(Target {
+      triple: triple.to_owned(),
+      is_cross: triple != host()?,
+    })
+  }
+
+  fn arch(&self) -> &str {
+    self.triple.split('-').next().unwrap_or_default()
+  }
+
+  /// The last component of the triple, e.g. `gnu`, `musl` or `msvc`.
+  fn env(&self) -> &str {
+    self.triple.rsplit('-').next().unwrap_or_default()
+  }
+
+  pub fn is_windows(&self) -> bool {
+    self.triple.contains("-windows")
+  }
+
+  fn is_macos(&self) -> bool {
+    self.triple.contains("-apple-darwin")
+  }
+
+  pub fn is_linux(&self) -> bool {
+    self.triple.contains("-linux")
+  }
+
+  fn is_musl(&self) -> bool {
+    self.env().starts_with("musl")
+  }
+
+  /// The architecture as Debian multiarch names it, which is what Python
+  /// uses in `EXT_SUFFIX` on Linux.
+  fn multiarch(&self) -> &str {
+    match self.arch() {
+      "i586" | "i686" => "i386",
+      "armv7" | "armv6" | "arm" => "arm",
+      arch => arch,
+    }
+  }
+
+  /// The architecture as GNU cross toolchains name it, e.g. `i686` in
+  /// `i686-linux-gnu-gcc` and `arm` in `arm-linux-gnueabihf-gcc`.
+  fn gcc_arch(&self) -> &str {
+    match self.arch() {
+      "armv7" | "armv6" => "arm",
+      arch => arch,
+    }
+  }
+
+  /// The extension module suffix a CPython `version` on this target expects,
+  /// e.g. `.cpython-311-aarch64-linux-gnu.so`.
+  pub fn ext_suffix(&self, (major, minor): (u32, u32)) -> String {
+    if self.is_windows() {
+      format!(".cp{}{}-{}.pyd", major, minor, self.wheel_platform())
+    } else if self.is_macos() {
+      format!(".cpython-{}{}-darwin.so", major, minor)
+    } else {
+      format!(
+        ".cpython-{}{}-{}-linux-{}.so",
+        major,
+        minor,
+        self.multiarch(),
+        self.env()
+      )
+    }
+  }
+
+  /// The untagged extension module suffix on this target.
+  pub fn plain_suffix(&self) -> &'static str {
+    if self.is_windows() {
+      ".pyd"
+    } else {
+      ".so"
+    }
+  }
+
+  /// The suffix for stable ABI extension modules on this target.
+  pub fn abi3_suffix(&self) -> &'static str {
+    if self.is_windows() {
+      ".pyd"
+    } else {
+      ".abi3.so"
+    }
+  }
+
+  /// The platform part of a wheel tag for this target, e.g.
+  /// `linux_aarch64`, using the oldest macOS each architecture supports.
+  pub fn wheel_platform(&self) -> String {
+    match self.arch() {
+      "x86_64" if self.is_windows() => "win_amd64".into(),
+      "aarch64" if self.is_windows() => "win_arm64".into(),
+      _ if self.is_windows() => "win32".into(),
+      "x86_64" if self.is_macos() => "macosx_10_12_x86_64".into(),
+      "aarch64" if self.is_macos() => "macosx_11_0_arm64".into(),
+      "i586" | "i686" => "linux_i686".into(),
+      "armv7" => "linux_armv7l".into(),
+      "powerpc64le" => "linux_ppc64le".into(),
+      arch => format!("linux_{}", arch),
+    }
+  }
+
+  /// The `[target]` section of the cargo config for this target: a cross
+  /// linker if one is installed under its usual name, and for musl a dynamic
+  /// C runtime, without which cargo cannot build a cdylib.
+  pub fn cargo_config(&self) -> String {
+    let mut config = String::new();
+    if self.is_cross {
+      let linker = if self.is_linux() {
+        Some(format!("{}-linux-{}-gcc", self.gcc_arch(), self.env()))
+      } else if self.is_windows() && self.env() == "gnu" {
+        Some(format!("{}-w64-mingw32-gcc", self.arch()))
+      } else {
+        None
+      };
+      if let Some(linker) = linker.filter(|linker| on_path(linker)) {
+        config.push_str(&format!("linker = \"{}\"\n", linker));
+      }
+    }
+    if self.is_musl() {
+      config.push_str("rustflags = [\"-C\", \"target-feature=-crt-static\"]\n");
+    }
+
+    if config.is_empty() {
+      config
+    } else {
+      format!("[target.{}]\n{}", self.triple, config)
+    }
+  }
+}
+
+/// The triple of the host rustc builds for by default.
+pub fn host() -> Result<String> {
+  let output = Command::new("rustc")
+    .arg("-vV")
+    .output()
+    .context("Could not run rustc")?;
+  String::from_utf8(output.stdout)?
+    .lines()
+    .find_map(|line| line.strip_prefix("host: "))
+    .map(str::to_owned)
+    .context("rustc -vV does not report a host")
+}
+
+/// The `EXT_SUFFIX` recorded in the `_sysconfigdata` module in `lib_dir`,
+/// which pyo3 also reads when cross compiling.
+pub fn sysconfig_ext_suffix(lib_dir: &Path) -> Option<String> {
+  fs::read_dir(lib_dir)
+    .ok()?
+    .filter_map(|entry| entry.ok())
+    .filter(|entry| {
+      let name = entry.file_name();
+      let name = name.to_string_lossy();
+      name.starts_with("_sysconfigdata") && name.ends_with(".py")
+    })
+    .find_map(|entry| {
+      let data = fs::read_to_string(entry.path()).ok()?;
+      let rest = &data[data.find("'EXT_SUFFIX':")? + "'EXT_SUFFIX':".len()..];
+      let rest = rest.trim_start().strip_prefix('\'')?;
+      Some(rest[..rest.find('\'')?].to_owned())
+    })
+}
+
+fn on_path(program: &str) -> bool {
+  env::var_os("PATH")
+    .is_some_and(|path| env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn target(triple: &str) -> Target {
+    Target {
+      triple: triple.into(),
+      is_cross: true,
+    }
+  }
+
+  #[test]
+  fn linker_names() {
+    assert_eq!(target("i686-unknown-linux-gnu").gcc_arch(), "i686");
+    assert_eq!(target("armv7-unknown-linux-gnueabihf").gcc_arch(), "arm");
+    assert_eq!(target("aarch64-unknown-linux-gnu").gcc_arch(), "aarch64");
+  }
+
+  #[test]
+  fn ext_suffixes() {
+    assert_eq!(
+      target("i686-unknown-linux-gnu").ext_suffix((3, 11)),
+      ".cpython-311-i386-linux-gnu.so"
+    );
+    assert_eq!(
+      target("armv7-unknown-linux-gnueabihf").ext_suffix((3, 12)),
+      ".cpython-312-arm-linux-gnueabihf.so"
+    );
+    assert_eq!(
+      target("x86_64-pc-windows-gnu").ext_suffix((3, 11)),
+      ".cp311-win_amd64.pyd"
+    );
+  }
+}
@@ -0,0 +1,56 @@
+use anyhow::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::sources;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Runs `build`, then runs it again every time one of `inputs` or the files
+/// they pull in changes.
+pub fn watch(inputs: &[&Path], mut build: impl FnMut() -> Result<Vec<PathBuf>>) -> Result<()> {
+  loop {
+    // Taken before building, so that saves made while cargo runs still count
+    // as changes once it is done.
+    let snapshot = modified_times(inputs);
+    let start = Instant::now();
+    match build() {
+      Ok(paths) => eprintln!(
+        "Built {} in {:.1}s",
+        paths
+          .iter()
+          .map(|path| path.display().to_string())
+          .collect::<Vec<_>>()
+          .join(", "),
+        start.elapsed().as_secs_f32()
+      ),
+      Err(err) => eprintln!("Build failed: {:#}", err),
+    }
+
+    while modified_times(inputs) == snapshot {
+      thread::sleep(POLL_INTERVAL);
+    }
+    // Give editors that save in several steps a moment to finish.
+    thread::sleep(POLL_INTERVAL);
+  }
+}
+
+fn modified_times(inputs: &[&Path]) -> Vec<(PathBuf, Option<SystemTime>)> {
+  inputs
+    .iter()
+    .flat_map(|input| {
+      let root = input.parent().unwrap_or_else(|| Path::new(""));
+      std::iter::once(input.to_path_buf()).chain(
+        sources::local_files(input)
+          .into_iter()
+          .map(|file| root.join(file)),
+      )
+    })
+    .map(|path| {
+      let modified = fs::metadata(&path).and_then(|meta| meta.modified()).ok();
+      (path, modified)
+    })
+    .collect()
+}
@@ -0,0 +1,147 @@
+use anyhow::Result;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// A distribution of a single extension module, as installed by a wheel.
+pub struct Distribution<'a> {
+  pub name: &'a str,
+  pub version: &'a str,
+  pub summary: Option<&'a str>,
+  /// The PEP 425 compatibility tag, e.g. `cp311-cp311-linux_x86_64`.
+  pub tag: &'a str,
+}
+
+impl Distribution<'_> {
+  /// The file name of the wheel, as specified by PEP 427.
+  pub fn file_name(&self) -> String {
+    format!(
+      "{}-{}-{}.whl",
+      escape(self.name),
+      escape(self.version),
+      self.tag
+    )
+  }
+
+  /// The name of the `.dist-info` directory.
+  pub fn dist_info(&self) -> String {
+    format!("{}-{}.dist-info", escape(self.name), escape(self.version))
+  }
+
+  /// `files`, which are installed at the root of site-packages, followed by
+  /// the `.dist-info` metadata describing them and ending with its `RECORD`.
+  pub fn entries(&self, files: &[(String, Vec<u8>)]) -> Vec<(String, Vec<u8>)> {
+    let dist_info = self.dist_info();
+
+    let mut metadata = format!(
+      "Metadata-Version: 2.1\nName: {}\nVersion: {}\n",
+      self.name, self.version
+    );
+    if let Some(summary) = self.summary {
+      metadata.push_str(&format!("Summary: {}\n", summary));
+    }
+
+    let mut wheel = format!(
+      "Wheel-Version: 1.0\nGenerator: {} {}\nRoot-Is-Purelib: false\n",
+      env!("CARGO_PKG_NAME"),
+      env!("CARGO_PKG_VERSION"),
+    );
+    // A compressed tag set such as `manylinux_2_17_x86_64.manylinux2014_x86_64`
+    // is listed as one tag per platform.
+    let (prefix, platforms) = self.tag.rsplit_once('-').unwrap_or(("", self.tag));
+    for platform in platforms.split('.') {
+      wheel.push_str(&format!("Tag: {}-{}\n", prefix, platform));
+    }
+
+    let mut entries = files.to_vec();
+    entries.push((format!("{}/METADATA", dist_info), metadata.into_bytes()));
+    entries.push((format!("{}/WHEEL", dist_info), wheel.into_bytes()));
+
+    let mut record = String::new();
+    for (name, contents) in &entries {
+      record.push_str(&format!(
+        "{},sha256={},{}\n",
+        name,
+        URL_SAFE_NO_PAD.encode(Sha256::digest(contents)),
+        contents.len()
+      ));
+    }
+    let record_name = format!("{}/RECORD", dist_info);
+    record.push_str(&format!("{},,\n", record_name));
+    entries.push((record_name, record.into_bytes()));
+
+    entries
+  }
+
+  /// Writes a wheel to `path` holding `files` and their metadata.
+  pub fn write_wheel(&self, path: &Path, files: &[(String, Vec<u8>)]) -> Result<()> {
+    let mut zip = ZipWriter::new(File::create(path)?);
+    for (name, contents) in self.entries(files) {
+      let options = FileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .unix_permissions(0o644);
+      zip.start_file(name, options)?;
+      zip.write_all(&contents)?;
+    }
+    zip.finish()?;
+
+    Ok(())
+  }
+}
+
+/// Escapes a name or version for use in a wheel file name.
+fn escape(component: &str) -> String {
+  component.replace('-', "_")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn entries_end_with_the_record() {
+    let dist = Distribution {
+      name: "my-mod",
+      version: "1.0",
+      summary: None,
+      tag: "cp311-cp311-manylinux_2_17_x86_64.manylinux2014_x86_64",
+    };
+    assert_eq!(
+      dist.file_name(),
+      "my_mod-1.0-cp311-cp311-manylinux_2_17_x86_64.manylinux2014_x86_64.whl"
+    );
+
+    let entries = dist.entries(&[("my_mod.so".into(), b"abc".to_vec())]);
+    let names = entries
+      .iter()
+      .map(|(name, _)| name.as_str())
+      .collect::<Vec<_>>();
+    assert_eq!(
+      names,
+      [
+        "my_mod.so",
+        "my_mod-1.0.dist-info/METADATA",
+        "my_mod-1.0.dist-info/WHEEL",
+        "my_mod-1.0.dist-info/RECORD",
+      ]
+    );
+
+    let wheel = String::from_utf8(entries[2].1.clone()).unwrap();
+    assert!(wheel
+      .contains("Tag: cp311-cp311-manylinux_2_17_x86_64\nTag: cp311-cp311-manylinux2014_x86_64\n"));
+
+    let record = String::from_utf8(entries[3].1.clone()).unwrap();
+    let lines = record.lines().collect::<Vec<_>>();
+    assert_eq!(
+      lines[0],
+      "my_mod.so,sha256=ungWv48Bz-pBQUDeXa4iI7ADYaOWF3qctBD_YfIAFa0,3"
+    );
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[3], "my_mod-1.0.dist-info/RECORD,,");
+  }
+}